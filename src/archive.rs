@@ -0,0 +1,234 @@
+use crate::logging::{self, Prefix};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Файл внутри распакованной зависимости, в котором хранится sha256 архива.
+pub const ARCHIVE_MARKER: &str = ".constructor-archive";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let name = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Ok(ArchiveFormat::TarXz)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(anyhow::anyhow!(
+                "Unsupported archive format: {} (expected .tar.gz, .tar.xz or .zip)",
+                url
+            ))
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+/// sha256 файла в виде шестнадцатеричной строки в нижнем регистре.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let digest = Sha256::digest(fs::read(path)?);
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Проверяет, что `dir` уже содержит распаковку архива с теми же sha256 и strip_prefix.
+pub fn is_extracted(dir: &Path, sha256: &str, strip_prefix: Option<&str>) -> bool {
+    fs::read_to_string(dir.join(ARCHIVE_MARKER))
+        .map(|marker| marker == marker_contents(sha256, strip_prefix))
+        .unwrap_or(false)
+}
+
+fn marker_contents(sha256: &str, strip_prefix: Option<&str>) -> String {
+    format!("{}\n{}\n", sha256.to_lowercase(), strip_prefix.unwrap_or_default())
+}
+
+/// Скачивает (или копирует для file://) архив зависимости `name` в `dest`.
+pub fn download(name: &str, url: &str, dest: &Path) -> Result<()> {
+    if let Some(path) = url.strip_prefix("file://") {
+        fs::copy(path, dest)
+            .map_err(|e| anyhow::anyhow!("Failed to copy archive {}: {}", path, e))?;
+        return Ok(());
+    }
+    let mut command = Command::new("curl");
+    command
+        .arg("--fail")
+        .arg("--location")
+        .arg("--silent")
+        .arg("--show-error")
+        .arg("--output")
+        .arg(dest)
+        .arg(url);
+    let status = logging::run_command(&mut command, Prefix::Target(name))?;
+    if !status.success() {
+        anyhow::bail!("Failed to download archive: {}", url);
+    }
+    Ok(())
+}
+
+/// Проверяет sha256 архива и распаковывает его в `dest`.
+/// Если указан `strip_prefix`, в `dest` попадает только содержимое этой поддиректории.
+pub fn verify_and_extract(
+    archive: &Path,
+    format: ArchiveFormat,
+    sha256: &str,
+    strip_prefix: Option<&str>,
+    dest: &Path,
+) -> Result<()> {
+    let actual = sha256_file(archive)?;
+    if !actual.eq_ignore_ascii_case(sha256) {
+        // Битый или подменённый архив не должен остаться в кеше загрузок
+        fs::remove_file(archive)?;
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected sha256 {}, got {}",
+            archive.display(),
+            sha256,
+            actual
+        );
+    }
+
+    let staging = staging_dir(dest);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let file = File::open(archive)?;
+    match format {
+        ArchiveFormat::TarGz => tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(&staging)?,
+        ArchiveFormat::TarXz => tar::Archive::new(xz2::read::XzDecoder::new(file)).unpack(&staging)?,
+        ArchiveFormat::Zip => zip::ZipArchive::new(file)?.extract(&staging)?,
+    }
+
+    let root = match strip_prefix {
+        Some(prefix) => staging.join(prefix.trim_matches('/')),
+        None => staging.clone(),
+    };
+    if !root.is_dir() {
+        fs::remove_dir_all(&staging)?;
+        anyhow::bail!(
+            "strip_prefix '{}' does not exist in archive {}",
+            strip_prefix.unwrap_or_default(),
+            archive.display()
+        );
+    }
+
+    if dest.exists() {
+        fs::remove_dir_all(dest)?;
+    }
+    fs::rename(&root, dest)?;
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::write(dest.join(ARCHIVE_MARKER), marker_contents(sha256, strip_prefix))?;
+    Ok(())
+}
+
+fn staging_dir(dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{}.extract", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("constructor-archive-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// tar.gz с файлами `mylib-1.0/<имя>`.
+    fn tar_gz(path: &Path, files: &[(&str, &str)]) {
+        let encoder = flate2::write::GzEncoder::new(File::create(path).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("mylib-1.0/{}", name), content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn detects_format_from_url() {
+        assert_eq!(ArchiveFormat::from_url("https://example.com/a-1.0.tar.gz").unwrap(), ArchiveFormat::TarGz);
+        assert_eq!(ArchiveFormat::from_url("https://example.com/a.TGZ").unwrap(), ArchiveFormat::TarGz);
+        assert_eq!(ArchiveFormat::from_url("https://example.com/a.tar.xz?download=1").unwrap(), ArchiveFormat::TarXz);
+        assert_eq!(ArchiveFormat::from_url("file:///tmp/a.txz").unwrap(), ArchiveFormat::TarXz);
+        assert_eq!(ArchiveFormat::from_url("https://example.com/a.zip#sha").unwrap(), ArchiveFormat::Zip);
+        let error = ArchiveFormat::from_url("https://example.com/a.tar.bz2").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unsupported archive format: https://example.com/a.tar.bz2 (expected .tar.gz, .tar.xz or .zip)"
+        );
+    }
+
+    #[test]
+    fn extracts_with_strip_prefix() {
+        let dir = temp_dir("strip");
+        let archive = dir.join("mylib.tar.gz");
+        tar_gz(&archive, &[("one.c", "int one;\n"), ("include/one.h", "int one;\n")]);
+        let sha256 = sha256_file(&archive).unwrap();
+        let dest = dir.join("deps/mylib");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+
+        verify_and_extract(&archive, ArchiveFormat::TarGz, &sha256.to_uppercase(), Some("mylib-1.0/"), &dest).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("one.c")).unwrap(), "int one;\n");
+        assert!(dest.join("include/one.h").is_file());
+        assert!(!staging_dir(&dest).exists());
+        assert!(is_extracted(&dest, &sha256, Some("mylib-1.0/")));
+        assert!(!is_extracted(&dest, &sha256, None));
+        assert!(!is_extracted(&dest, &"0".repeat(64), Some("mylib-1.0/")));
+
+        // Без strip_prefix в dest попадает корневая директория архива
+        verify_and_extract(&archive, ArchiveFormat::TarGz, &sha256, None, &dest).unwrap();
+        assert!(dest.join("mylib-1.0/one.c").is_file());
+        assert!(!dest.join("one.c").exists());
+
+        let error = verify_and_extract(&archive, ArchiveFormat::TarGz, &sha256, Some("other"), &dest).unwrap_err();
+        assert!(error.to_string().starts_with("strip_prefix 'other' does not exist in archive"), "{}", error);
+        assert!(!staging_dir(&dest).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let dir = temp_dir("mismatch");
+        let archive = dir.join("mylib.tar.gz");
+        tar_gz(&archive, &[("one.c", "int one;\n")]);
+        let actual = sha256_file(&archive).unwrap();
+        let expected = "0".repeat(64);
+        let dest = dir.join("mylib");
+        let error = verify_and_extract(&archive, ArchiveFormat::TarGz, &expected, None, &dest).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Checksum mismatch for {}: expected sha256 {}, got {}", archive.display(), expected, actual)
+        );
+        // Битый архив удаляется из кеша загрузок, а dest не создаётся
+        assert!(!archive.exists());
+        assert!(!dest.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
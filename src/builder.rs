@@ -1,705 +1,1247 @@
-use crate::config::{BuildConfig, TargetConfig};
+use crate::archive::{self, ArchiveFormat};
+use crate::cache::TargetInputs;
+use crate::depbuild::{self, BuildLayout};
+use crate::diagnostics::Diagnostics;
+use crate::objects::{self, ObjectFile};
+use crate::timing::Timings;
+use crate::{checks, commands, configure, logging, options, platform, resources};
+use crate::logging::Prefix;
+use crate::usage::{self, UsageRequirements};
+use crate::vendor;
+use crate::config::{self, BuildConfig, BuildSystem, Dependency, DependencySource, OptionValue, OsTarget, TargetConfig, TargetKind};
 use anyhow::Result;
-use std::process::Command;
-use std::path::Path;
-use std::fs::{self, File};
+use log::{debug, error, info, warn};
+use std::process::{Command, ExitStatus};
+use std::path::{Path, PathBuf};
+use std::fs;
 use std::thread;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::io::{Read, Write};
+
+/// Директория, куда `constructor vendor` складывает зависимости по умолчанию.
+pub const DEFAULT_VENDOR_DIR: &str = "vendor";
+
+/// Префикс `constructor install` по умолчанию.
+pub const DEFAULT_INSTALL_PREFIX: &str = "/usr/local";
 
 pub struct Builder
 {
     config: BuildConfig,
-    force_rebuild: bool
+    force_rebuild: bool,
+    offline: bool,
+    vendor_dir: String,
+    load_options: config::LoadOptions,
+    /// --dry-run: печатать команды и решения кеша, ничего не запуская и не записывая
+    dry_run: bool,
+    /// --explain: печатать, какие файлы и настройки вызвали пересборку
+    explain: bool,
+    /// Время загрузки зависимостей, скриптов, компиляции и линковки
+    timings: Timings,
+    /// Разобранный stderr компилятора по таргетам
+    diagnostics: Diagnostics,
 }
 
 pub trait DefaultBuilder
 {
-    fn new(config: BuildConfig, force_rebuild: bool) -> Builder;
+    fn new(config: BuildConfig, force_rebuild: bool) -> Self;
     fn build(&self) -> Result<()>;
-    fn fetch_dependencies(&self) -> Result<()>;
-    fn fetch_git_dependency(&self, dep: &crate::config::Dependency) -> Result<()>;
-    fn build_target(&self, target: &TargetConfig) -> Result<()>; 
+    fn fetch_dependencies(&self, build: bool) -> Result<Vec<UsageRequirements>>;
+    fn fetch_git_dependency(&self, dep: &Dependency) -> Result<UsageRequirements>;
+    fn fetch_local_dependency(&self, dep: &Dependency) -> Result<UsageRequirements>;
+    fn fetch_archive_dependency(&self, dep: &Dependency) -> Result<UsageRequirements>;
+    fn build_dependency(&self, dep: &Dependency, fetched: UsageRequirements) -> Result<UsageRequirements>;
+    fn build_target(&self, target: &TargetConfig, usage: &[UsageRequirements]) -> Result<bool>;
 }
 
 impl DefaultBuilder for Builder
 {
     fn new(config: BuildConfig, force_rebuild: bool) -> Self {
-        Self { config, force_rebuild}
+        Self {
+            config,
+            force_rebuild,
+            offline: false,
+            vendor_dir: DEFAULT_VENDOR_DIR.to_string(),
+            load_options: config::LoadOptions::default(),
+            dry_run: false,
+            explain: false,
+            timings: Timings::new(),
+            diagnostics: Diagnostics::default(),
+        }
     }
 
     fn build(&self) -> Result<()> {
-        self.fetch_dependencies()?;
-        let project_name = self.config.project.name.clone();
-        let version = self.config.project.version.clone();
-        let mut version_parts = version.split('.');
-        let major_version = version_parts.next().unwrap_or("0");
-        let minor_version = version_parts.next().unwrap_or("0");
-        let patch_version = version_parts.next().unwrap_or("0");
-        let version_define_major = format!("{}_VERSION_MAJOR={}", project_name.to_uppercase(), major_version);
-        let version_define_minor = format!("{}_VERSION_MINOR={}", project_name.to_uppercase(), minor_version);
-        let version_define_patch = format!("{}_VERSION_PATCH={}", project_name.to_uppercase(), patch_version);
-        let mut handles = vec![];
-        for target in &self.config.targets {
-            if let Some(false) = target.enabled { continue; }
-            let mut target = target.clone();
-            let mut defines = target.defines.clone().unwrap_or_default();
-            defines.push(version_define_major.clone());
-            defines.push(version_define_minor.clone());
-            defines.push(version_define_patch.clone());
-            target.defines = Some(defines);
-            let dependencies = self.config.dependencies.clone();
-            let force_rebuild = self.force_rebuild;
-            let project_name = project_name.clone();
-            let version = version.clone();
-            let target_name = target.name.clone();
-            let handle = std::thread::spawn(move || {
-                build_target_static(target, dependencies, force_rebuild, project_name, version)
-            });
-            handles.push((target_name, handle));
+        // Узлы графа: внешние зависимости и уже собранные библиотечные таргеты
+        let (mut nodes, all_dependencies) = self.dependency_nodes()?;
+        // Версия проекта, опции и проверки попадают в сгенерированные заголовки
+        let check_values = checks::run_all(&self.config, self.force_rebuild, self.dry_run)?;
+        configure::generate_all(&self.config, &check_values, self.dry_run)?;
+        // Сгенерированные командами файлы могут быть исходниками таргетов
+        commands::run_all(&self.config, self.force_rebuild, self.dry_run)?;
+
+        let mut pending = self.resolve_build_targets(&check_values)?;
+        let target_names: HashSet<String> = pending.iter().map(|target| target.name.clone()).collect();
+        for target in &pending {
+            for dep in target.deps.iter().flatten() {
+                if self.config.targets.iter().any(|t| &t.name == dep) && !target_names.contains(dep) {
+                    anyhow::bail!("Target '{}' depends on disabled or skipped target '{}'", target.name, dep);
+                }
+            }
         }
+
+        // Собираем волнами: таргет запускается, когда собраны все библиотечные таргеты из его deps
         let mut errors = vec![];
-        for (name, handle) in handles {
-            if let Err(e) = handle.join().map_err(|_| anyhow::anyhow!("Thread panicked")).and_then(|r| r) {
-                errors.push((name, e));
+        let mut failed: HashSet<String> = HashSet::new();
+        while !pending.is_empty() {
+            let mut ready = vec![];
+            let mut blocked = vec![];
+            for target in pending {
+                let target_deps: Vec<&String> = target.deps.iter().flatten().filter(|dep| target_names.contains(*dep)).collect();
+                if let Some(dep) = target_deps.iter().find(|dep| failed.contains(**dep)) {
+                    errors.push((target.name.clone(), anyhow::anyhow!("dependency '{}' failed to build", dep)));
+                    failed.insert(target.name.clone());
+                } else if target_deps.iter().all(|dep| nodes.contains_key(*dep)) {
+                    ready.push(target);
+                } else {
+                    blocked.push(target);
+                }
+            }
+            pending = blocked;
+            if ready.is_empty() {
+                if !pending.is_empty() {
+                    let names: Vec<&str> = pending.iter().map(|target| target.name.as_str()).collect();
+                    anyhow::bail!("Dependency cycle between targets: {}", names.join(", "));
+                }
+                break;
+            }
+
+            let results = thread::scope(|scope| {
+                let mut handles = vec![];
+                for target in &ready {
+                    let roots = usage::roots(target, &all_dependencies);
+                    let nodes = &nodes;
+                    let handle = scope.spawn(move || {
+                        let usage = usage::collect(&target.name, &roots, nodes)?;
+                        let rebuilt = self.timings.record("target", &target.name, &target.name, || self.build_target(target, &usage));
+                        self.diagnostics.print(&target.name);
+                        let rebuilt = rebuilt?;
+                        if target.is_library() {
+                            // Потребители наследуют те же узлы, с которыми таргет собирался
+                            let mut target_usage = UsageRequirements::for_target(target, Path::new(""), roots)?;
+                            // В dry-run артефакт не меняется, поэтому отмечаем пересборку сами,
+                            // чтобы зависящие таргеты тоже показали пересборку
+                            if self.dry_run && rebuilt {
+                                target_usage.fingerprint = !target_usage.fingerprint;
+                            }
+                            Ok(Some(target_usage))
+                        } else {
+                            Ok(None)
+                        }
+                    });
+                    handles.push((target.name.clone(), handle));
+                }
+                handles
+                    .into_iter()
+                    .map(|(name, handle)| (name, handle.join().map_err(|_| anyhow::anyhow!("Thread panicked")).and_then(|r| r)))
+                    .collect::<Vec<_>>()
+            });
+            for (name, result) in results {
+                match result {
+                    Ok(Some(target_usage)) => {
+                        nodes.insert(name, target_usage);
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        failed.insert(name.clone());
+                        errors.push((name, e));
+                    },
+                }
             }
         }
         if !errors.is_empty() {
             for (name, e) in errors {
-                eprintln!("Build failed for target '{}': {}", name, e);
+                error!(target = name.as_str(); "Build failed: {:#}", e);
             }
             anyhow::bail!("Some targets failed to build");
         }
         Ok(())
     }
 
-    fn fetch_dependencies(&self) -> Result<()> {
+    /// Загружает зависимости; с `build` — ещё и собирает те, у которых есть секция build.
+    fn fetch_dependencies(&self, build: bool) -> Result<Vec<UsageRequirements>> {
+        let mut requirements = vec![];
         if let Some(deps) = &self.config.dependencies {
-            let mut handles = vec![];
-            for dep in deps {
-                match dep.source.as_str() {
-                    "git" => {
-                        let dep = dep.clone();
-                        let force_rebuild = self.force_rebuild;
-                        let handle = std::thread::spawn(move || {
-                            fetch_git_dependency_static(dep.clone(), force_rebuild)
-                        });
-                        handles.push(handle);
-                    },
-                    "local" => println!("Local dependency: {}", dep.name),
-                    "system" => println!("System dependency: {}", dep.name),
-                    _ => println!("Unknown dependency type: {}", dep.source),
+            thread::scope(|scope| -> Result<()> {
+                let build_dependency = move |dep, usage| if build { self.build_dependency(dep, usage) } else { Ok(usage) };
+                let mut handles = vec![];
+                for dep in deps {
+                    match dep.source {
+                        DependencySource::Git => {
+                            let handle = scope.spawn(move || self.timings.record("fetch", &dep.name, &dep.name, || self.fetch_git_dependency(dep)).and_then(|usage| build_dependency(dep, usage)).map(Some));
+                            handles.push(handle);
+                        },
+                        DependencySource::Local => {
+                            let handle = scope.spawn(move || self.timings.record("fetch", &dep.name, &dep.name, || self.fetch_local_dependency(dep)).and_then(|usage| build_dependency(dep, usage)).map(Some));
+                            handles.push(handle);
+                        },
+                        DependencySource::Archive => {
+                            let handle = scope.spawn(move || self.timings.record("fetch", &dep.name, &dep.name, || self.fetch_archive_dependency(dep)).and_then(|usage| build_dependency(dep, usage)).map(Some));
+                            handles.push(handle);
+                        },
+                        DependencySource::System => {
+                            info!(target = dep.name.as_str(); "Using system dependency");
+                            requirements.push(UsageRequirements::for_dependency(dep, None, 0));
+                        },
+                    }
                 }
-            }
-            for handle in handles {
-                handle.join().map_err(|_| anyhow::anyhow!("Thread panicked"))??;
-            }
+                for handle in handles {
+                    if let Some(dep_usage) = handle.join().map_err(|_| anyhow::anyhow!("Thread panicked"))?? {
+                        requirements.push(dep_usage);
+                    }
+                }
+                Ok(())
+            })?;
         }
-        Ok(())
+        Ok(requirements)
     }
 
-    fn fetch_git_dependency(&self, dep: &crate::config::Dependency) -> Result<()> {
-        
+    fn fetch_git_dependency(&self, dep: &Dependency) -> Result<UsageRequirements> {
+
         let dep_dir = format!("deps/{}", dep.name);
         let dep_path = Path::new(&dep_dir);
-        
-        if self.force_rebuild && dep_path.exists() {
-            println!("Force rebuilding dependency: {}...", dep.name);
-            std::fs::remove_dir_all(&dep_dir)?;
+
+        // В офлайн-режиме deps/ не удаляем: взять зависимость заново будет неоткуда
+        if self.force_rebuild && !self.offline && dep_path.exists() {
+            info!(target = dep.name.as_str(); "Removing {} to fetch it again (--force)", dep_dir);
+            self.remove_dir_all(&dep.name, &dep_dir)?;
+        }
+
+        if self.offline && !dep_path.exists() {
+            self.copy_from_vendor(dep, dep_path)?;
         }
-    
+
+        // Завендоренная копия используется как есть, пока её ревизия совпадает с rev из конфига.
+        // Отпечаток — хеш коммита, как и у клона, чтобы переход между ними не пересобирал потребителей
+        if let Some(locked) = vendor::vendored_revision(dep_path) {
+            match &dep.rev {
+                Some(rev) if !locked.matches(rev) => {
+                    if self.offline {
+                        anyhow::bail!(
+                            "Vendored copy of '{}' is locked at {}, but the config requests {}",
+                            dep.name,
+                            locked,
+                            rev
+                        );
+                    }
+                    info!(target = dep.name.as_str(); "Vendored copy is locked at {}, fetching {}", locked, rev);
+                    self.remove_dir_all(&dep.name, &dep_dir)?;
+                },
+                _ => {
+                    info!(target = dep.name.as_str(); "Using vendored copy at {}", locked);
+                    let mut hasher = DefaultHasher::new();
+                    locked.commit.hash(&mut hasher);
+                    return Ok(UsageRequirements::for_dependency(dep, Some(dep_path), hasher.finish()));
+                },
+            }
+        }
+
         // Если директория уже существует
         if dep_path.exists() {
         // Проверяем, является ли это git репозиторием
-            if dep_path.join(".git").exists() {
-                println!("Dependency {} already exists, pulling latest changes...", dep.name);
-            
-            // Обновляем существующий репозиторий
-                let status = Command::new("git")
-                    .current_dir(&dep_dir)
-                    .arg("pull")
-                    .status()?;
-                
-                if !status.success() {
-                    anyhow::bail!("Failed to update dependency: {}", dep.name);
-                }
-            } else {
+            if !dep_path.join(".git").exists() {
                 anyhow::bail!(
                     "Dependency directory '{}' exists but is not a git repository. \
                     Please remove it manually or specify a different location.",
                     dep_dir
                 );
             }
+            if self.offline {
+                info!(target = dep.name.as_str(); "Using existing {} as is (offline)", dep_dir);
+            } else if dep.rev.is_some() {
+                info!(target = dep.name.as_str(); "Fetching changes into existing {}", dep_dir);
+                let status = self.spawn(&dep.name, Command::new("git")
+                    .envs(self.config.env_for(None))
+                    .current_dir(&dep_dir)
+                    .arg("fetch")
+                    .arg("--tags"))?;
+
+                if !status.success() {
+                    anyhow::bail!("Failed to update dependency: {}", dep.name);
+                }
+            } else {
+                info!(target = dep.name.as_str(); "Pulling changes into existing {}", dep_dir);
+
+            // Обновляем существующий репозиторий
+                let status = self.spawn(&dep.name, Command::new("git")
+                    .envs(self.config.env_for(None))
+                    .current_dir(&dep_dir)
+                    .arg("pull"))?;
+
+                if !status.success() {
+                    anyhow::bail!("Failed to update dependency: {}", dep.name);
+                }
+            }
         } else {
         // Создаем директорию deps, если её нет
-            std::fs::create_dir_all("deps")?;
-        
-            println!("Cloning {} from {}...", dep.name, dep.location);
-            let status = Command::new("git")
+            self.create_dir_all("deps")?;
+
+            info!(target = dep.name.as_str(); "Cloning {}", dep.location()?);
+            let status = self.spawn(&dep.name, Command::new("git")
+                .envs(self.config.env_for(None))
                 .arg("clone")
-                .arg(&dep.location)
-                .arg(&dep_dir)
-                .status()?;
-            
+                .arg(dep.location()?)
+                .arg(&dep_dir))?;
+
             if !status.success() {
                 anyhow::bail!("Failed to clone dependency: {}", dep.name);
             }
         }
-    
-        Ok(())
+
+        if let Some(rev) = &dep.rev {
+            let status = self.spawn(&dep.name, Command::new("git")
+                .envs(self.config.env_for(None))
+                .current_dir(&dep_dir)
+                .arg("checkout")
+                .arg("--quiet")
+                .arg(rev))?;
+            if !status.success() {
+                anyhow::bail!("Failed to check out {} for dependency: {}", rev, dep.name);
+            }
+        }
+
+        // Потребители пересобираются, когда зависимость переезжает на другой коммит.
+        // В dry-run репозитория может ещё не быть, тогда отпечаток неизвестен
+        let mut hasher = DefaultHasher::new();
+        if !self.dry_run || dep_path.join(".git").exists() {
+            vendor::resolve_git_revision(dep_path, "HEAD")?.hash(&mut hasher);
+        }
+        Ok(UsageRequirements::for_dependency(dep, Some(dep_path), hasher.finish()))
     }
 
-    fn build_target(&self, target: &TargetConfig) -> Result<()> {
-        println!("Building target: {}", target.name);
-        
-        // --- Кеширование ---
+    fn fetch_local_dependency(&self, dep: &Dependency) -> Result<UsageRequirements> {
+        let location = dep.location()?;
+        let dep_path = Path::new(location);
+        if !dep_path.is_dir() {
+            anyhow::bail!(
+                "Local dependency '{}' points to '{}', which is not a directory",
+                dep.name,
+                location
+            );
+        }
+        info!(target = dep.name.as_str(); "Using local directory {}", location);
+
+        // Зависимость с секцией build собирается в build_dependency
+        let nested_usage = if dep.build.is_none() {
+            self.build_nested_workspace(dep, dep_path)?
+        } else {
+            vec![]
+        };
+
+        // Отпечаток считается после сборки, чтобы пересобранные библиотеки тоже попадали в ключ.
+        // Зависимость с секцией build может собираться прямо в своём дереве (make): её результаты
+        // в отпечаток не входят, иначе каждая сборка давала бы новую конфигурацию
         let mut hasher = DefaultHasher::new();
-        // Хешируем исходники
-        for source in &target.sources {
-            source.hash(&mut hasher);
-            if let Ok(mut file) = File::open(source) {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf)?;
-                buf.hash(&mut hasher);
-            }
+        match &dep.build {
+            Some(build) => {
+                let outputs: Vec<PathBuf> = build.outputs.iter().flatten().map(|output| dep_path.join(output)).collect();
+                hash_directory(dep_path, &mut hasher, &|path| outputs.iter().any(|output| output == path) || is_build_product(path))?;
+            },
+            None => hash_directory(dep_path, &mut hasher, &|_| false)?,
         }
-        // Хешируем defines
-        if let Some(defines) = &target.defines {
-            defines.hash(&mut hasher);
+
+        let mut requirements = UsageRequirements::for_dependency(dep, Some(dep_path), hasher.finish());
+        for target_usage in nested_usage {
+            requirements.merge(target_usage);
         }
-        // Хешируем compiler_flags
-        if let Some(flags) = &target.compiler_flags {
-            flags.hash(&mut hasher);
+        Ok(requirements)
+    }
+
+    fn fetch_archive_dependency(&self, dep: &Dependency) -> Result<UsageRequirements> {
+        let url = dep.url.as_deref().ok_or_else(|| {
+            anyhow::anyhow!("Archive dependency '{}' requires 'url'", dep.name)
+        })?;
+        let sha256 = dep.sha256.as_deref().ok_or_else(|| {
+            anyhow::anyhow!("Archive dependency '{}' requires 'sha256'", dep.name)
+        })?;
+        let format = ArchiveFormat::from_url(url)?;
+        let dep_dir = format!("deps/{}", dep.name);
+        let dep_path = Path::new(&dep_dir);
+
+        // Распакованное дерево переиспользуется, пока sha256 и strip_prefix в конфиге не поменялись
+        let vendored = Path::new(&self.vendor_dir).join(&dep.name);
+        if !self.force_rebuild && archive::is_extracted(dep_path, sha256, dep.strip_prefix.as_deref()) {
+            info!(target = dep.name.as_str(); "Reusing extracted {}", dep_dir);
+        } else if self.offline && archive::is_extracted(&vendored, sha256, dep.strip_prefix.as_deref()) {
+            self.copy_from_vendor(dep, dep_path)?;
+        } else {
+            let archive_path = self.download_archive(dep, url, sha256, format)?;
+            info!(target = dep.name.as_str(); "Extracting into {}", dep_dir);
+            if self.dry_run {
+                info!(target = dep.name.as_str(); "[dry-run] extract {} into {}", archive_path.display(), dep_dir);
+            } else {
+                archive::verify_and_extract(&archive_path, format, sha256, dep.strip_prefix.as_deref(), dep_path)
+                    .map_err(|e| anyhow::anyhow!("Dependency '{}': {}", dep.name, e))?;
+            }
         }
-        // Хешируем includes
-        if let Some(includes) = &target.includes {
-            includes.hash(&mut hasher);
+
+        let mut hasher = DefaultHasher::new();
+        sha256.to_lowercase().hash(&mut hasher);
+        dep.strip_prefix.hash(&mut hasher);
+
+        Ok(UsageRequirements::for_dependency(dep, Some(dep_path), hasher.finish()))
+    }
+
+    fn build_dependency(&self, dep: &Dependency, fetched: UsageRequirements) -> Result<UsageRequirements> {
+        let Some(build) = &dep.build else {
+            return Ok(fetched);
+        };
+        let source_dir = dep.root()?;
+        // Конфигурация определяется системой сборки, опциями и содержимым зависимости
+        let env = self.config.env_for(None);
+        let layout = BuildLayout::new(dep, build, &source_dir, &env, fetched.fingerprint);
+        let mut nested_usage = vec![];
+        if build.system == BuildSystem::Constructor {
+            nested_usage = self.build_nested_workspace(dep, &source_dir)?;
+            if nested_usage.is_empty() && config::find_workspace(&source_dir).is_none() {
+                anyhow::bail!("Dependency '{}' uses build system 'constructor' but has no WORKSPACE", dep.name);
+            }
+        } else if !self.force_rebuild && layout.is_built(build) {
+            info!(target = dep.name.as_str(); "Already built for this configuration, reusing {}", layout.cache_dir.display());
+        } else {
+            info!(target = dep.name.as_str(); "Building with {}", build.system);
+            let system = build.system.to_string();
+            self.timings.record("build", &dep.name, &system, || depbuild::run(dep, build, &source_dir, &layout, &env, self.dry_run))?;
         }
-        // Хешируем linker_flags
-        if let Some(linker_flags) = &target.linker_flags {
-            linker_flags.hash(&mut hasher);
+        if !self.dry_run {
+            layout.check_outputs(dep, build)?;
+            fs::create_dir_all(&layout.cache_dir)?;
+            layout.mark_built()?;
         }
-        // Хешируем зависимости
-        if let Some(deps) = &self.config.dependencies {
-            for dep in deps {
-                dep.name.hash(&mut hasher);
-                dep.source.hash(&mut hasher);
-                dep.location.hash(&mut hasher);
+
+        // Потребители пересобираются, когда меняется конфигурация или собранные библиотеки
+        let mut hasher = DefaultHasher::new();
+        layout.cache_dir.hash(&mut hasher);
+        for output in build.outputs.iter().flatten() {
+            let path = layout.result_dir.join(output);
+            // В dry-run зависимость могла быть ещё не собрана
+            if self.dry_run && !path.exists() {
+                continue;
             }
+            fs::read(path)?.hash(&mut hasher);
+        }
+        let mut requirements = UsageRequirements::for_dependency(dep, Some(&layout.result_dir), hasher.finish());
+        for target_usage in nested_usage {
+            requirements.merge(target_usage);
         }
-        let hash = hasher.finish();
+        Ok(requirements)
+    }
+
+    /// Собирает таргет, если он изменился. Возвращает, был ли таргет пересобран
+    /// (с --dry-run — был бы).
+    fn build_target(&self, target: &TargetConfig, usage: &[UsageRequirements]) -> Result<bool> {
+        // --- Кеширование ---
+        let inputs = self.target_inputs(target, usage)?;
         let cache_file_path = format!("{}/.build_cache_{}.txt", target.out_dir, target.name);
         let mut need_rebuild = true;
-        let mut prev_hash: Option<u64> = None;
         // Если force_rebuild == true, кеширование полностью игнорируется и всегда происходит пересборка
-        if !self.force_rebuild {
-            if let Ok(mut cache_file) = File::open(&cache_file_path) {
-                let mut prev_hash_str = String::new();
-                cache_file.read_to_string(&mut prev_hash_str)?;
-                if let Ok(h) = prev_hash_str.trim().parse::<u64>() {
-                    prev_hash = Some(h);
-                    if h == hash {
-                        println!("Target '{}' is up to date (cache hit), skipping build.", target.name);
-                        need_rebuild = false;
-                    }
-                }
+        if self.force_rebuild {
+            if self.explain {
+                info!(target = target.name.as_str(); "Rebuilding because of --force");
             }
-        } else {
-            // Кеширование игнорируется, всегда пересобираем
-        }
-        if need_rebuild {
-            // Подробный лог изменений
-            if let Some(old_hash) = prev_hash {
-                println!("Cache miss for target '{}'. Причина: изменения в:", target.name);
-                // Проверяем по частям
-                let mut log_part = |label: &str, old: u64, new: u64| {
-                    if old != new {
-                        println!("  - {} (hash изменился)", label);
-                    }
-                };
-                // Исходники
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                for source in &target.sources {
-                    source.hash(&mut hasher_old);
-                    source.hash(&mut hasher_new);
-                    if let Ok(mut file) = File::open(source) {
-                        let mut buf = Vec::new();
-                        file.read_to_end(&mut buf)?;
-                        buf.hash(&mut hasher_old);
-                        buf.hash(&mut hasher_new);
+        } else if let Some((prev_hash, previous)) = TargetInputs::read(Path::new(&cache_file_path)) {
+            if prev_hash == inputs.hash() && Path::new(&target.output_path()).exists() {
+                info!(target = target.name.as_str(); "Up to date (cache hit)");
+                need_rebuild = false;
+            } else if prev_hash == inputs.hash() {
+                info!(target = target.name.as_str(); "Output {} is missing, rebuilding", target.output_path());
+            } else {
+                info!(target = target.name.as_str(); "Cache miss, rebuilding");
+                if self.explain {
+                    // Кеш старого формата хранит только общий хеш
+                    if previous.components.is_empty() {
+                        info!(target = target.name.as_str(); "  - the previous build did not record its inputs");
                     }
-                }
-                log_part("sources", hasher_old.finish(), hasher_new.finish());
-                // Defines
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                if let Some(defines) = &target.defines {
-                    defines.hash(&mut hasher_old);
-                    defines.hash(&mut hasher_new);
-                }
-                log_part("defines", hasher_old.finish(), hasher_new.finish());
-                // Compiler flags
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                if let Some(flags) = &target.compiler_flags {
-                    flags.hash(&mut hasher_old);
-                    flags.hash(&mut hasher_new);
-                }
-                log_part("compiler_flags", hasher_old.finish(), hasher_new.finish());
-                // Includes
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                if let Some(includes) = &target.includes {
-                    includes.hash(&mut hasher_old);
-                    includes.hash(&mut hasher_new);
-                }
-                log_part("includes", hasher_old.finish(), hasher_new.finish());
-                // Linker flags
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                if let Some(linker_flags) = &target.linker_flags {
-                    linker_flags.hash(&mut hasher_old);
-                    linker_flags.hash(&mut hasher_new);
-                }
-                log_part("linker_flags", hasher_old.finish(), hasher_new.finish());
-                // Dependencies
-                let mut hasher_old = DefaultHasher::new();
-                let mut hasher_new = DefaultHasher::new();
-                if let Some(deps) = &self.config.dependencies {
-                    for dep in deps {
-                        dep.name.hash(&mut hasher_old);
-                        dep.source.hash(&mut hasher_old);
-                        dep.location.hash(&mut hasher_old);
-                        dep.name.hash(&mut hasher_new);
-                        dep.source.hash(&mut hasher_new);
-                        dep.location.hash(&mut hasher_new);
+                    for change in inputs.changes(&previous) {
+                        info!(target = target.name.as_str(); "  - {}", change);
                     }
                 }
-                log_part("dependencies", hasher_old.finish(), hasher_new.finish());
             }
+        } else if self.explain {
+            info!(target = target.name.as_str(); "Not built before");
         }
         // --- Конец кеширования ---
-        
+
+        // Окружение проекта и таргета получают все команды; скрипты запускаются в working_dir,
+        // а компилятор — из корня WORKSPACE, чтобы пути к исходникам и include не менялись
+        let env = self.target_env(target);
+
+        if !need_rebuild {
+            self.run_scripts(target, "post-build", &target.post_build_scripts, &env)?;
+            return Ok(false);
+        }
+
         // Выполнение pre_build_scripts
-        if let Some(scripts) = &target.pre_build_scripts {
-            for script in scripts {
-                println!("Running pre-build script: {}", script);
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(script)
-                    .status()?;
-                if !status.success() {
-                    anyhow::bail!("Pre-build script failed: {}", script);
-                }
+        self.run_scripts(target, "pre-build", &target.pre_build_scripts, &env)?;
+
+        info!(target = target.name.as_str(); "Building");
+        let compile_args = compile_arguments(target, usage);
+
+        // out_dir может быть вложенным, например build/${profile}/bin
+        if Path::new(&target.out_dir).is_dir() {
+            debug!(target = target.name.as_str(); "Output directory {} already exists", target.out_dir);
+        } else if self.dry_run {
+            info!(target = target.name.as_str(); "[dry-run] create directory {}", target.out_dir);
+        } else {
+            match fs::create_dir_all(&target.out_dir) {
+                Ok(()) => debug!(target = target.name.as_str(); "Created output directory {}", target.out_dir),
+                Err(e) => warn!(target = target.name.as_str(); "Failed to create output directory {}: {}", target.out_dir, e),
             }
         }
-        
-        let mut command = Command::new(target.compiler.clone());
-        
-        // Добавляем флаги компилятора
-        if let Some(flags) = &target.compiler_flags {
-            for flag in flags {
-                command.arg(flag);
-            }
+
+        // Выходной файл; custom_output может указывать в другую директорию
+        let output = target.output_path();
+        if let Some(parent) = Path::new(&output).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            self.create_dir_all(parent)?;
         }
-        
-        // Добавляем определения
-        if let Some(defines) = &target.defines {
-            for define in defines {
-                command.arg(format!("-D{}", define));
+
+        // Каждый исходник компилируется в свой объектный файл. Объект пересобирается, только если
+        // изменилась команда компиляции или исходник и включённые им заголовки новее объекта.
+        self.create_dir_all(objects::object_dir(target))?;
+        let mut object_paths = vec![];
+        for object in ObjectFile::for_target(target) {
+            let mut hasher = DefaultHasher::new();
+            target.compiler.hash(&mut hasher);
+            compile_args.hash(&mut hasher);
+            env.hash(&mut hasher);
+            object.source.hash(&mut hasher);
+            let command_hash = hasher.finish();
+            if !self.force_rebuild && object.is_up_to_date(command_hash) {
+                debug!(target = target.name.as_str(); "{} is up to date", object.object);
+            } else {
+                info!(target = target.name.as_str(); "Compiling {}", object.source);
+                let mut command = Command::new(&target.compiler);
+                command
+                    .envs(env.iter().cloned())
+                    .args(&compile_args)
+                    .arg("-MMD").arg("-MF").arg(&object.depfile)
+                    .arg("-c").arg(&object.source)
+                    .arg("-o").arg(&object.object);
+                self.run_build_command(&mut command, &target.name, "compile", &object.source)?;
+                if !self.dry_run {
+                    object.record(command_hash)?;
+                }
             }
+            object_paths.push(object.object);
         }
-        
-        // Добавляем include-директории
-        if let Some(includes) = &target.includes {
-            for include in includes {
-                command.arg("-I").arg(include);
+
+        if target.kind == TargetKind::Staticlib {
+            // ar дописывает в существующий архив, поэтому старый удаляем
+            if Path::new(&output).exists() && !self.dry_run {
+                fs::remove_file(&output)?;
+            }
+            info!(target = target.name.as_str(); "Archiving {}", output);
+            let mut command = Command::new("ar");
+            command.envs(env.iter().cloned()).arg("rcs").arg(&output).args(&object_paths);
+            self.run_build_command(&mut command, &target.name, "archive", &output)?;
+        } else {
+            let mut command = Command::new(&target.compiler);
+            command.envs(env.iter().cloned());
+            // Флаги компилятора вроде -pthread или -fsanitize нужны и при линковке
+            command.args(target.compiler_flags.iter().flatten());
+            if target.kind == TargetKind::Dynamiclib {
+                command.arg("-shared");
             }
+
+            // Добавляем объектные файлы, за ними библиотеки и флаги линковки
+            command.args(&object_paths);
+            command.args(link_arguments(target, usage));
+
+            command.arg("-o").arg(&output);
+            info!(target = target.name.as_str(); "Linking {}", output);
+            self.run_build_command(&mut command, &target.name, "link", &output)?;
+        }
+
+        if !self.dry_run {
+            info!(target = target.name.as_str(); "Built {}", output);
         }
-        
-        // Добавляем исходные файлы
+
+        // extra_steps выполняются сразу после линковки и только при пересборке, в отличие
+        // от post_build_scripts, которые запускаются и при попадании в кеш
+        self.run_scripts(target, "extra", &target.extra_steps, &env)?;
+
+        // Выполнение post_build_scripts
+        self.run_scripts(target, "post-build", &target.post_build_scripts, &env)?;
+        if self.dry_run {
+            return Ok(true);
+        }
+        // После успешной сборки сохраняем хеш; он считается заново, потому что
+        // компилятор только что обновил depfile со списком заголовков
+        self.target_inputs(target, usage)?.write(Path::new(&cache_file_path))?;
+        Ok(true)
+    }
+}
+
+impl Builder {
+    /// Составляющие ключа кеша таргета: содержимое исходников и заголовков из depfile, компилятор,
+    /// флаги, зависимости, унаследованные требования и значения опций. По ним --explain
+    /// показывает, что именно изменилось с прошлой сборки.
+    fn target_inputs(&self, target: &TargetConfig, usage: &[UsageRequirements]) -> Result<TargetInputs> {
+        let mut inputs = TargetInputs::default();
+        // Исходники; пропавший файл тоже меняет ключ
         for source in &target.sources {
-            command.arg(source);
-        }
-        
-        // Добавляем MacOS frameworks
-        if target.os_target.to_lowercase() == "macos" {
-            if let Some(frameworks) = &target.frameworks {
-                for fw in frameworks {
-                    command.arg("-framework").arg(fw);
+            inputs.add(format!("source {}", source), fs::read(source).ok());
+        }
+        // Заголовки, которые исходники включали при прошлой сборке (из depfile)
+        for object in ObjectFile::for_target(target) {
+            for input in object.inputs() {
+                let name = input.to_string_lossy();
+                if !target.sources.iter().any(|source| *source == name) {
+                    inputs.add(format!("header {}", name), fs::read(&input).ok());
                 }
             }
         }
-        
-        // Флаги линковки
-        if let Some(linker_flags) = &target.linker_flags {
-            for flag in linker_flags {
-                command.arg(flag);
+        inputs.add("compiler", &target.compiler);
+        inputs.add("defines", &target.defines);
+        inputs.add("compiler_flags", &target.compiler_flags);
+        inputs.add("includes", &target.includes);
+        inputs.add("linker_flags", &target.linker_flags);
+        for dep in self.config.dependencies.iter().flatten() {
+            inputs.add(format!("dependency {}", dep.name), (&dep.source, &dep.location, &dep.url, &dep.sha256, &dep.rev));
+        }
+        // Публичные требования таргета и всё, что он унаследовал от зависимостей
+        inputs.add("public_requirements", (&target.public_includes, &target.public_defines, &target.public_link_libraries));
+        for dep_usage in usage {
+            let mut hasher = DefaultHasher::new();
+            dep_usage.hash_into(&mut hasher);
+            inputs.add(format!("usage {}", dep_usage.name), hasher.finish());
+        }
+        inputs.add("options", &self.config.option_values);
+        // Окружение и рабочая директория шагов сборки
+        inputs.add("env", self.target_env(target));
+        inputs.add("working_dir", &target.working_dir);
+        // Путь к артефакту и дополнительные шаги
+        inputs.add("output", target.output_path());
+        inputs.add("extra_steps", &target.extra_steps);
+        Ok(inputs)
+    }
+
+    /// Загружает и собирает зависимости. Возвращает узлы графа по именам и имена
+    /// [[dependencies]], которые получают таргеты без явных deps.
+    fn dependency_nodes(&self) -> Result<(HashMap<String, UsageRequirements>, Vec<String>)> {
+        let mut nodes: HashMap<String, UsageRequirements> = HashMap::new();
+        for dep_usage in self.fetch_dependencies(true)? {
+            nodes.insert(dep_usage.name.clone(), dep_usage);
+        }
+        let all_dependencies = self.config.dependencies
+            .iter()
+            .flatten()
+            .map(|dep| dep.name.clone())
+            .filter(|name| nodes.contains_key(name))
+            .collect();
+        Ok((nodes, all_dependencies))
+    }
+
+    /// Таргеты для текущей платформы в том виде, в котором они собираются: с условными блоками,
+    /// ресурсами и определениями проекта. Как -D опции и проверки с define = true получают только
+    /// таргеты с project_defines = true: смена опции пересобирает все их объекты, а не только
+    /// включающие заголовок.
+    fn resolve_build_targets(&self, check_values: &BTreeMap<String, OptionValue>) -> Result<Vec<TargetConfig>> {
+        let mut project_defines = options::defines(self.config.options.as_ref(), &self.config.option_values);
+        project_defines.extend(checks::defines(&self.config, check_values));
+        let condition_values = platform::condition_values(&self.config.option_values, check_values);
+        let mut targets = platform::resolve_targets(&self.config, &condition_values)?;
+        for target in &mut targets {
+            if target.project_defines == Some(true) {
+                target.defines.get_or_insert_with(Vec::new).extend(project_defines.iter().cloned());
             }
+            add_resources(target, self.dry_run)?;
         }
+        Ok(targets)
+    }
 
-        match fs::create_dir(target.out_dir.clone()) {
-            Ok(()) => println!("Directory created successfully"),
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::AlreadyExists {
-                    println!("Directory already exists");
-                } else {
-                    eprintln!("Error creating directory: {}", e);
+    /// Переменные окружения для команд таргета: сначала `env` проекта, затем таргета,
+    /// так что таргет переопределяет одноимённые переменные проекта.
+    fn target_env(&self, target: &TargetConfig) -> Vec<(String, String)> {
+        self.config.env_for(Some(target))
+    }
+
+    /// Включает офлайн-режим: зависимости берутся только из deps/ или из `vendor_dir`.
+    pub fn with_offline(mut self, offline: bool, vendor_dir: &str) -> Self {
+        self.offline = offline;
+        self.vendor_dir = vendor_dir.to_string();
+        self
+    }
+
+    /// Параметры, с которыми загружаются конфиги вложенных WORKSPACE.
+    pub fn with_load_options(mut self, load_options: &config::LoadOptions) -> Self {
+        self.load_options = load_options.clone();
+        self
+    }
+
+    /// Включает режим --dry-run: команды только печатаются, файлы не пишутся.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Включает --explain: для каждой пересборки печатается, что изменилось с прошлой.
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Печатает самые долгие шаги сборки и, если указан `trace`, записывает их
+    /// в формате Chrome trace event. В dry-run ничего не запускалось, так что и отчёта нет.
+    pub fn report_timings(&self, trace: Option<&str>) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        self.timings.print_summary();
+        match trace {
+            Some(path) => self.timings.write_trace(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Записывает диагностики компилятора всех таргетов в SARIF-файл `path` ("-" — stdout).
+    pub fn write_diagnostics(&self, path: &str) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        self.diagnostics.write_sarif(path)
+    }
+
+    /// Запускает команду таргета или зависимости `name`, её вывод идёт в лог с префиксом `name`.
+    /// Команда видна с -v, а с --dry-run только печатается и считается успешной.
+    fn spawn(&self, name: &str, command: &mut Command) -> Result<ExitStatus> {
+        if self.dry_run {
+            info!(target = name; "[dry-run] {:?}", command);
+            return Ok(ExitStatus::default());
+        }
+        debug!(target = name; "Running {:?}", command);
+        Ok(logging::run_command(command, Prefix::Target(name))?)
+    }
+
+    /// Запускает шаг сборки таргета (`compile`, `archive` или `link`) и записывает его время.
+    /// stderr перехватывается и разбирается в диагностики таргета; они выводятся одним
+    /// блоком, когда таргет собран или упал.
+    fn run_build_command(&self, command: &mut Command, target_name: &str, step: &'static str, name: &str) -> Result<()> {
+        if self.dry_run {
+            info!(target = target_name; "[dry-run] {:?}", command);
+            return Ok(());
+        }
+        debug!(target = target_name; "Running {:?}", command);
+        let output = self.timings.record(step, target_name, name, || command.output())?;
+        logging::log_output(Prefix::Target(target_name), "stdout", &output.stdout);
+        self.diagnostics.add(target_name, &command.get_program().to_string_lossy(), &output.stderr);
+        if !output.status.success() {
+            anyhow::bail!("Failed to build target: {}", target_name);
+        }
+        Ok(())
+    }
+
+    fn run_scripts(&self, target: &TargetConfig, stage: &str, scripts: &Option<Vec<String>>, env: &[(String, String)]) -> Result<()> {
+        let working_dir = target.working_dir.as_deref();
+        if let Some(scripts) = scripts {
+            for script in scripts {
+                info!(target = target.name.as_str(); "Running {} script: {}", stage, script);
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script).envs(env.iter().cloned());
+                if let Some(dir) = working_dir {
+                    command.current_dir(dir);
+                }
+                let span = format!("{}: {}", stage, script);
+                let status = self.timings.record("script", &target.name, &span, || self.spawn(&target.name, &mut command)).map_err(|e| match working_dir {
+                    Some(dir) => anyhow::anyhow!("Failed to run {} script in working_dir '{}': {}", stage, dir, e),
+                    None => anyhow::anyhow!("Failed to run {} script: {}", stage, e),
+                })?;
+                if !status.success() {
+                    anyhow::bail!("{} script failed ({}): {}", stage, status, script);
                 }
             }
         }
-        
-        // Выходной файл
-        let output = match target.kind.as_str() {
-            "executable" => format!("{}/{}", target.out_dir, target.name),
-            "staticlib" => format!("{}/lib{}.a", target.out_dir ,target.name),
-            "dynamiclib" => format!("{}/lib{}.so", target.out_dir, target.name),
-            _ => anyhow::bail!("Unknown target kind: {}", target.kind),
+        Ok(())
+    }
+
+    fn create_dir_all(&self, dir: impl AsRef<Path>) -> Result<()> {
+        if !self.dry_run {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, name: &str, dir: impl AsRef<Path>) -> Result<()> {
+        if self.dry_run {
+            info!(target = name; "[dry-run] remove {}", dir.as_ref().display());
+        } else {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Если в `dir` есть свой WORKSPACE, собирает его отдельным процессом из этой же директории,
+    /// чтобы пути в его конфиге оставались относительными к ней, и возвращает публичные
+    /// требования его библиотечных таргетов.
+    fn build_nested_workspace(&self, dep: &Dependency, dir: &Path) -> Result<Vec<UsageRequirements>> {
+        let Some(workspace) = config::find_workspace(dir) else {
+            return Ok(vec![]);
         };
-        
-        command.arg("-o").arg(&output);
-        
-        // Печатаем сгенерированную команду
-        println!("[build] Generated command: {:?}", command);
-        
-        // Запускаем компиляцию
-        let status = command.status()?;
+        // Оверлеи и --set из командной строки относятся только к основному WORKSPACE
+        let nested_options = config::LoadOptions { overlays: vec![], set: vec![], ..self.load_options.clone() };
+        let nested = config::load_config(&workspace.to_string_lossy(), &nested_options)?;
+        info!(target = dep.name.as_str(); "Building with {}", workspace.display());
+        let mut command = Command::new(std::env::current_exe()?);
+        command.current_dir(dir).arg("--config").arg(workspace.file_name().unwrap_or_default());
+        command.arg("--profile").arg(&self.load_options.profile);
+        if self.force_rebuild {
+            command.arg("--force");
+        }
+        if self.offline {
+            command.arg("--offline");
+        }
+        command.args(logging::forwarded_args());
+        // Вложенный WORKSPACE сам печатает свой план
+        if self.dry_run {
+            command.arg("--dry-run");
+        }
+        // Вложенный constructor пишет в лог сам, в том же формате (см. forwarded_args),
+        // поэтому его вывод не перехватывается
+        let status = self.timings.record("build", &dep.name, &workspace.to_string_lossy(), || command.status())?;
         if !status.success() {
-            anyhow::bail!("Failed to build target: {}", target.name);
+            anyhow::bail!("Failed to build dependency: {}", dep.name);
         }
-        
-        println!("Successfully built: {}", output);
-        
-        // Выполнение post_build_scripts
-        if let Some(scripts) = &target.post_build_scripts {
-            for script in scripts {
-                println!("Running post-build script: {}", script);
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(script)
-                    .status()?;
-                if !status.success() {
-                    anyhow::bail!("Post-build script failed: {}", script);
+        // Проверки вложенный WORKSPACE выполняет сам; для выбора таргетов хватает опций и платформы
+        let condition_values = platform::condition_values(&nested.option_values, &BTreeMap::new());
+        let mut nested_usage = vec![];
+        for target in platform::resolve_targets(&nested, &condition_values)? {
+            if target.is_library() {
+                let deps = target.deps.clone().unwrap_or_default();
+                nested_usage.push(UsageRequirements::for_target(&target, dir, deps)?);
+            }
+        }
+        Ok(nested_usage)
+    }
+
+    fn copy_from_vendor(&self, dep: &Dependency, dep_path: &Path) -> Result<()> {
+        let vendored = Path::new(&self.vendor_dir).join(&dep.name);
+        if !vendored.is_dir() {
+            anyhow::bail!(
+                "Dependency '{}' is not available offline: neither {} nor {} exists. \
+                Run `constructor vendor` or build once without --offline.",
+                dep.name,
+                dep_path.display(),
+                vendored.display()
+            );
+        }
+        info!(target = dep.name.as_str(); "Copying vendored copy from {}", vendored.display());
+        if self.dry_run {
+            info!(target = dep.name.as_str(); "[dry-run] copy {} to {}", vendored.display(), dep_path.display());
+            return Ok(());
+        }
+        if dep_path.exists() {
+            fs::remove_dir_all(dep_path)?;
+        }
+        vendor::copy_dir(&vendored, dep_path, &|_| false)
+    }
+
+    /// Скачивает архив зависимости в deps/.archives, если там ещё нет архива с тем же sha256,
+    /// и возвращает путь к нему.
+    fn download_archive(&self, dep: &Dependency, url: &str, sha256: &str, format: ArchiveFormat) -> Result<PathBuf> {
+        let archives_dir = Path::new("deps/.archives");
+        self.create_dir_all(archives_dir)?;
+        let short_sha = &sha256[..sha256.len().min(12)];
+        let archive_path = archives_dir.join(format!("{}-{}.{}", dep.name, short_sha, format.extension()));
+        let cached = archive_path.exists()
+            && archive::sha256_file(&archive_path)?.eq_ignore_ascii_case(sha256);
+        if !cached {
+            if self.offline && !url.starts_with("file://") {
+                anyhow::bail!(
+                    "Dependency '{}' is not available offline: deps/{} is not extracted and not vendored in {}",
+                    dep.name,
+                    dep.name,
+                    self.vendor_dir
+                );
+            }
+            info!(target = dep.name.as_str(); "Downloading {}", url);
+            if self.dry_run {
+                info!(target = dep.name.as_str(); "[dry-run] download {} to {}", url, archive_path.display());
+            } else {
+                archive::download(&dep.name, url, &archive_path)?;
+            }
+        }
+        Ok(archive_path)
+    }
+
+    /// Копирует все разрешённые зависимости на их зафиксированных ревизиях в `dir`.
+    /// Зависимости только загружаются, не собираются: в vendor-директорию попадают исходники
+    /// без результатов сборки.
+    pub fn vendor(&self, dir: &str) -> Result<()> {
+        self.fetch_dependencies(false)?;
+        let vendor_path = Path::new(dir);
+        fs::create_dir_all(vendor_path)?;
+        for dep in self.config.dependencies.iter().flatten() {
+            let dep_path = Path::new("deps").join(&dep.name);
+            let dest = vendor_path.join(&dep.name);
+            match dep.source {
+                DependencySource::Git | DependencySource::Archive => {},
+                _ => {
+                    info!(target = dep.name.as_str(); "Skipping {} dependency: nothing to vendor", dep.source);
+                    continue;
+                },
+            }
+            if dep.source == DependencySource::Archive {
+                let url = dep.url.as_deref().unwrap_or_default();
+                let sha256 = dep.sha256.as_deref().unwrap_or_default();
+                if !self.force_rebuild && archive::is_extracted(&dest, sha256, dep.strip_prefix.as_deref()) {
+                    info!(target = dep.name.as_str(); "Already vendored in {}", dest.display());
+                    continue;
                 }
+                // deps/<name> могла собираться на месте (make), поэтому распаковываем архив заново
+                let format = ArchiveFormat::from_url(url)?;
+                let archive_path = self.download_archive(dep, url, sha256, format)?;
+                archive::verify_and_extract(&archive_path, format, sha256, dep.strip_prefix.as_deref(), &dest)
+                    .map_err(|e| anyhow::anyhow!("Dependency '{}': {}", dep.name, e))?;
+                info!(target = dep.name.as_str(); "Vendored into {}", dest.display());
+                continue;
+            }
+            if dest.exists() {
+                fs::remove_dir_all(&dest)?;
+            }
+            if vendor::vendored_revision(&dep_path).is_some() {
+                // Без .git выгрузить дерево нельзя; результаты сборки на месте не копируем
+                let outputs: Vec<PathBuf> = dep.build.iter().flat_map(|build| build.outputs.iter().flatten()).map(|output| dep_path.join(output)).collect();
+                vendor::copy_dir(&dep_path, &dest, &|path| outputs.iter().any(|output| output == path) || is_build_product(path))?;
+                info!(target = dep.name.as_str(); "Vendored into {}", dest.display());
+            } else {
+                // В маркер пишется разрешённый коммит: тег или ветка могут сдвинуться
+                let commit = vendor::resolve_git_revision(&dep_path, dep.rev.as_deref().unwrap_or("HEAD"))?;
+                vendor::export_git_tree(&dep_path, &commit, &dest)?;
+                let locked = vendor::VendoredRevision { commit, rev: dep.rev.clone() };
+                vendor::write_vendor_marker(&dest, &locked)?;
+                info!(target = dep.name.as_str(); "Vendored at {} into {}", locked, dest.display());
             }
         }
-        // После успешной сборки сохраняем хеш
-        let mut cache_file = File::create(&cache_file_path)?;
-        writeln!(cache_file, "{}", hash)?;
         Ok(())
     }
-}
 
-impl Builder {
+    /// Копирует артефакты таргетов (с учётом custom_output) в `prefix`: исполняемые файлы в bin/,
+    /// библиотеки в lib/. Без `names` устанавливаются все включённые таргеты, которые были собраны.
+    pub fn install(&self, prefix: &str, names: &[String]) -> Result<()> {
+        for name in names {
+            if !self.config.targets.iter().any(|target| &target.name == name) {
+                anyhow::bail!("Cannot install unknown target '{}'", name);
+            }
+        }
+        for target in self.config.targets.iter().filter(|target| target.enabled != Some(false)) {
+            if !names.is_empty() && !names.contains(&target.name) {
+                continue;
+            }
+            let output = target.output_path();
+            let source = Path::new(&output);
+            if !source.is_file() {
+                // Таргет мог быть пропущен из-за os_target
+                if names.is_empty() {
+                    info!(target = target.name.as_str(); "Not built, skipping install");
+                    continue;
+                }
+                anyhow::bail!("Target '{}' has no artifact at {}", target.name, output);
+            }
+            let dir = Path::new(prefix).join(if target.is_library() { "lib" } else { "bin" });
+            fs::create_dir_all(&dir)?;
+            let dest = dir.join(source.file_name().unwrap_or_default());
+            fs::copy(source, &dest)
+                .map_err(|e| anyhow::anyhow!("Failed to install {} to {}: {}", output, dest.display(), e))?;
+            info!(target = target.name.as_str(); "Installed {}", dest.display());
+        }
+        Ok(())
+    }
+
+    /// Запускает артефакт исполняемого таргета (с учётом custom_output) с аргументами `args`
+    /// и окружением таргета. Без `name` запускается единственный исполняемый таргет.
+    pub fn run(&self, name: Option<&str>, args: &[String]) -> Result<()> {
+        let executables: Vec<&TargetConfig> = self
+            .config
+            .targets
+            .iter()
+            .filter(|target| target.enabled != Some(false) && target.kind == TargetKind::Executable)
+            .collect();
+        let target = match name {
+            Some(name) => executables.iter().find(|target| target.name == name).copied().ok_or_else(|| {
+                anyhow::anyhow!("'{}' is not an enabled executable target", name)
+            })?,
+            None => match executables.as_slice() {
+                [target] => target,
+                [] => anyhow::bail!("There is no executable target to run"),
+                _ => {
+                    let names: Vec<&str> = executables.iter().map(|target| target.name.as_str()).collect();
+                    anyhow::bail!("Several executable targets, choose one: {}", names.join(", "));
+                },
+            },
+        };
+        let output = target.output_path();
+        // "app.out" без директории иначе искался бы в PATH
+        let program = match Path::new(&output) {
+            path if path.is_relative() => Path::new(".").join(path),
+            path => path.to_path_buf(),
+        };
+        info!(target = target.name.as_str(); "Running {}", output);
+        // Программа пользователя получает терминал как есть, её вывод не проходит через лог
+        let status = Command::new(&program)
+            .args(args)
+            .envs(self.target_env(target))
+            .status()
+            .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", output, e))?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {}", output, status);
+        }
+        Ok(())
+    }
+
     pub fn clean_cache(&self) -> Result<()> {
         for target in &self.config.targets {
+            // Артефакт (с учётом custom_output) и объектные файлы таргета
+            let output = target.output_path();
+            if Path::new(&output).is_file() {
+                info!(target = target.name.as_str(); "Removing output {}", output);
+                fs::remove_file(&output)?;
+            }
+            for dir in [objects::object_dir(target), resources::gen_dir(target)] {
+                if Path::new(&dir).is_dir() {
+                    info!(target = target.name.as_str(); "Removing generated files {}", dir);
+                    fs::remove_dir_all(&dir)?;
+                }
+            }
             let pattern = format!("{}/.build_cache_{}*.txt", target.out_dir, target.name);
             for entry in glob::glob(&pattern)? {
                 match entry {
                     Ok(path) => {
                         if path.exists() {
-                            println!("Removing cache file: {}", path.display());
+                            info!(target = target.name.as_str(); "Removing cache file {}", path.display());
                             fs::remove_file(&path)?;
                         }
                     },
-                    Err(e) => eprintln!("Glob error: {}", e),
+                    Err(e) => warn!(target = target.name.as_str(); "Cannot read cache file: {}", e),
                 }
             }
         }
+        commands::clean(&self.config)?;
+        if Path::new(checks::CHECKS_CACHE_FILE).exists() {
+            info!("Removing cache file {}", checks::CHECKS_CACHE_FILE);
+            fs::remove_file(checks::CHECKS_CACHE_FILE)?;
+        }
         Ok(())
     }
 
+    /// Генерирует Makefile, который собирает таргеты так же, как `build`: с теми же
+    /// определениями, ресурсами и требованиями зависимостей. Зависимости загружаются и собираются
+    /// при генерации, configure_files генерируются сразу, а [[commands]] становятся правилами make.
     pub fn generate_makefile(&self) -> Result<()> {
         let mut makefile = String::new();
         let project_name = &self.config.project.name;
-        makefile.push_str(&format!("PROJECT_NAME = {}\n", project_name));
-        for target in &self.config.targets {
-            if let Some(false) = target.enabled { continue; }
-            makefile.push_str(&format!("CC = {}\n", target.compiler));
-            // CFLAGS
-            let mut cflags = String::new();
-            if let Some(flags) = &target.compiler_flags {
-                for flag in flags {
-                    cflags.push_str(flag);
-                    cflags.push(' ');
-                }
-            }
-            makefile.push_str(&format!("CFLAGS = {}\n", cflags.trim_end()));
-            // LDFLAGS
-            let mut ldflags = String::new();
-            if let Some(flags) = &target.linker_flags {
-                for flag in flags {
-                    ldflags.push_str(flag);
-                    ldflags.push(' ');
-                }
-            }
-            // FRAMEWORKS (macOS)
-            if target.os_target.to_lowercase() == "macos" {
-                if let Some(frameworks) = &target.frameworks {
-                    for fw in frameworks {
-                        ldflags.push_str(&format!(" -framework {}", fw));
-                    }
-                }
-            }
-            makefile.push_str(&format!("LDFLAGS = {}\n", ldflags.trim_end()));
-            // DEFINES
-            let mut defines = String::new();
-            if let Some(defs) = &target.defines {
-                for d in defs {
-                    defines.push_str(&format!(" -D{}", d));
-                }
+        makefile.push_str(&format!("PROJECT_NAME = {}\n\n", project_name));
+        let (mut nodes, all_dependencies) = self.dependency_nodes()?;
+        let check_values = checks::run_all(&self.config, self.force_rebuild, self.dry_run)?;
+        configure::generate_all(&self.config, &check_values, self.dry_run)?;
+        let configured: Vec<&str> = self.config.configure_files.iter().flatten().map(|file| file.output.as_str()).collect();
+        let mut targets = self.resolve_build_targets(&check_values)?;
+
+        // Правила пишутся в порядке сборки, чтобы требования библиотечных таргетов
+        // были известны раньше их потребителей
+        let target_names: HashSet<String> = targets.iter().map(|target| target.name.clone()).collect();
+        let mut ordered: Vec<TargetConfig> = vec![];
+        while !targets.is_empty() {
+            let (ready, blocked): (Vec<TargetConfig>, Vec<TargetConfig>) = targets
+                .into_iter()
+                .partition(|target| target.deps.iter().flatten().filter(|dep| target_names.contains(*dep)).all(|dep| nodes.contains_key(dep)));
+            if ready.is_empty() {
+                let names: Vec<&str> = blocked.iter().map(|target| target.name.as_str()).collect();
+                anyhow::bail!("Dependency cycle between targets: {}", names.join(", "));
             }
-            makefile.push_str(&format!("DEFINES = {}\n", defines.trim_end()));
-            // INCLUDES
-            let mut includes = String::new();
-            if let Some(incs) = &target.includes {
-                for inc in incs {
-                    includes.push_str(&format!(" -I{}", inc));
+            for target in ready {
+                let roots = usage::roots(&target, &all_dependencies);
+                let usage = usage::collect(&target.name, &roots, &nodes)?;
+                self.write_target_rules(&mut makefile, &target, &usage, &configured);
+                if target.is_library() {
+                    nodes.insert(target.name.clone(), UsageRequirements::for_target(&target, Path::new(""), roots)?);
                 }
+                ordered.push(target);
             }
-            makefile.push_str(&format!("INCLUDES = {}\n", includes.trim_end()));
-            // SOURCES
-            makefile.push_str(&format!("SOURCES = {}\n", target.sources.join(" ")));
-            // OUTPUT
-            let output = match target.kind.as_str() {
-                "executable" => format!("{}/{}", target.out_dir, target.name),
-                "staticlib" => format!("{}/lib{}.a", target.out_dir, target.name),
-                "dynamiclib" => format!("{}/lib{}.so", target.out_dir, target.name),
-                _ => continue,
-            };
-            makefile.push_str(&format!("OUTPUT = {}\n", output));
-            makefile.push_str("\n");
-            // Цель
-            makefile.push_str(&format!("{}: $(SOURCES)\n", target.name));
-            makefile.push_str("\t$(CC) $(CFLAGS) $(DEFINES) $(INCLUDES) $(SOURCES) $(LDFLAGS) -o $(OUTPUT)\n\n");
+            targets = blocked;
         }
+        let targets = ordered;
         // Собираем имена целей и выходные файлы
         let mut target_names = Vec::new();
         let mut outputs = Vec::new();
-        for target in &self.config.targets {
-            if let Some(false) = target.enabled { continue; }
+        for target in &targets {
             target_names.push(target.name.clone());
-            let output = match target.kind.as_str() {
-                "executable" => format!("{}/{}", target.out_dir, target.name),
-                "staticlib" => format!("{}/lib{}.a", target.out_dir, target.name),
-                "dynamiclib" => format!("{}/lib{}.so", target.out_dir, target.name),
-                _ => continue,
-            };
+            let output = target.output_path();
             outputs.push(output);
         }
         // .PHONY
         makefile.push_str(&format!(".PHONY: {} clean\n\n", target_names.join(" ")));
+        // Правила для [[commands]]: make сам запустит генератор, если выход нужен таргету
+        for command in self.config.commands.iter().flatten() {
+            let inputs: Vec<String> = command
+                .inputs
+                .iter()
+                .flatten()
+                .map(|input| if input.contains(['*', '?', '[']) { format!("$(wildcard {})", input) } else { input.clone() })
+                .collect();
+            makefile.push_str(&format!("{} &: {}\n", command.outputs.join(" "), inputs.join(" ")));
+            let parents: BTreeSet<&Path> = command
+                .outputs
+                .iter()
+                .filter_map(|output| Path::new(output).parent())
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .collect();
+            for parent in parents {
+                makefile.push_str(&format!("\t@mkdir -p {}\n", parent.display()));
+            }
+            makefile.push_str(&format!("\t{}\n\n", make_escape(&command.run)));
+            outputs.extend(command.outputs.iter().cloned());
+        }
         // clean
         makefile.push_str("clean:\n");
         makefile.push_str(&format!("\trm -f {}\n", outputs.join(" ")));
+        let object_dirs: Vec<String> = targets.iter().map(objects::object_dir).collect();
+        makefile.push_str(&format!("\trm -rf {}\n", object_dirs.join(" ")));
         std::fs::write("Makefile", makefile)?;
-        println!("Makefile сгенерирован.");
+        info!("Generated Makefile");
         Ok(())
     }
-}
 
-fn build_target_static(target: crate::config::TargetConfig, dependencies: Option<Vec<crate::config::Dependency>>, force_rebuild: bool, project_name: String, version: String) -> anyhow::Result<()> {
-    // --- Копия логики build_target, но без self ---
-    use std::process::Command;
-    use std::path::Path;
-    use std::fs::{self, File};
-    use std::io::{Read, Write};
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    // --- Кеширование ---
-    let mut hasher = DefaultHasher::new();
-    for source in &target.sources {
-        source.hash(&mut hasher);
-        if let Ok(mut file) = File::open(source) {
-            let mut buf = Vec::new();
-            file.read_to_end(&mut buf)?;
-            buf.hash(&mut hasher);
-        }
-    }
-    if let Some(defines) = &target.defines {
-        defines.hash(&mut hasher);
-    }
-    if let Some(flags) = &target.compiler_flags {
-        flags.hash(&mut hasher);
-    }
-    if let Some(includes) = &target.includes {
-        includes.hash(&mut hasher);
-    }
-    if let Some(linker_flags) = &target.linker_flags {
-        linker_flags.hash(&mut hasher);
-    }
-    if let Some(deps) = &dependencies {
-        for dep in deps {
-            dep.name.hash(&mut hasher);
-            dep.source.hash(&mut hasher);
-            dep.location.hash(&mut hasher);
-        }
-    }
-    let hash = hasher.finish();
-    let cache_file_path = format!("{}/.build_cache_{}.txt", target.out_dir, target.name);
-    let mut need_rebuild = true;
-    let mut prev_hash: Option<u64> = None;
-    if !force_rebuild {
-        if let Ok(mut cache_file) = File::open(&cache_file_path) {
-            let mut prev_hash_str = String::new();
-            cache_file.read_to_string(&mut prev_hash_str)?;
-            if let Ok(h) = prev_hash_str.trim().parse::<u64>() {
-                prev_hash = Some(h);
-                if h == hash {
-                    println!("Target '{}' is up to date (cache hit), skipping build.", target.name);
-                    need_rebuild = false;
-                }
-            }
+    /// Правила таргета: компиляция каждого исходника в объектный файл и линковка (или архив)
+    /// теми же командами, что и в `build_target`. Окружение таргета экспортируется в его правила.
+    fn write_target_rules(&self, makefile: &mut String, target: &TargetConfig, usage: &[UsageRequirements], configured: &[&str]) {
+        let name = &target.name;
+        let output = target.output_path();
+        let objects = ObjectFile::for_target(target);
+        makefile.push_str(&format!("{}_CC = {}\n", name, target.compiler));
+        makefile.push_str(&format!("{}_CFLAGS = {}\n", name, make_escape(&compile_arguments(target, usage).join(" "))));
+        makefile.push_str(&format!("{}_LDFLAGS = {}\n", name, make_escape(&link_arguments(target, usage).join(" "))));
+        makefile.push_str(&format!("{}_OUTPUT = {}\n", name, output));
+        for (key, value) in self.target_env(target) {
+            makefile.push_str(&format!("$({}_OUTPUT): export {} = {}\n", name, key, make_escape(&value)));
         }
-    } else {
-        // Кеширование игнорируется, всегда пересобираем
-    }
-    if need_rebuild {
-        if let Some(old_hash) = prev_hash {
-            println!("Cache miss for target '{}'. Причина: изменения в:", target.name);
-            // ... (логика подробного лога изменений, можно вынести из старого build_target)
+        // Таргет пересобирается при изменении исходников, сгенерированных заголовков
+        // и библиотечных таргетов, от которых он зависит
+        let library_outputs = target
+            .deps
+            .iter()
+            .flatten()
+            .filter_map(|dep| self.config.targets.iter().find(|other| &other.name == dep && other.is_library()))
+            .map(|dep| dep.output_path());
+        let prerequisites: Vec<String> = target
+            .sources
+            .iter()
+            .cloned()
+            .chain(configured.iter().map(|output| output.to_string()))
+            .chain(library_outputs)
+            .collect();
+        makefile.push_str(&format!("\n{}: $({}_OUTPUT)\n\n", name, name));
+        makefile.push_str(&format!("$({}_OUTPUT): {}\n", name, prerequisites.join(" ")));
+        let mut dirs = BTreeSet::from([objects::object_dir(target)]);
+        if let Some(parent) = Path::new(&output).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            dirs.insert(parent.display().to_string());
         }
-    }
-    if !need_rebuild {
-        if let Some(scripts) = &target.post_build_scripts {
-            for script in scripts {
-                println!("Running post-build script: {}", script);
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(script)
-                    .status()?;
-                if !status.success() {
-                    anyhow::bail!("Post-build script failed: {}", script);
-                }
-            }
+        makefile.push_str(&format!("\t@mkdir -p {}\n", dirs.into_iter().collect::<Vec<_>>().join(" ")));
+        for object in &objects {
+            makefile.push_str(&format!("\t$({}_CC) $({}_CFLAGS) -c {} -o {}\n", name, name, object.source, object.object));
         }
-        return Ok(());
-    }
-    // --- pre_build_scripts ---
-    if let Some(scripts) = &target.pre_build_scripts {
-        for script in scripts {
-            println!("Running pre-build script: {}", script);
-            let status = Command::new("sh")
-                .arg("-c")
-                .arg(script)
-                .status()?;
-            if !status.success() {
-                anyhow::bail!("Pre-build script failed: {}", script);
+        let object_paths: Vec<&str> = objects.iter().map(|object| object.object.as_str()).collect();
+        if target.kind == TargetKind::Staticlib {
+            makefile.push_str(&format!("\trm -f $@\n\tar rcs $@ {}\n\n", object_paths.join(" ")));
+        } else {
+            let mut link = target.compiler_flags.clone().unwrap_or_default();
+            if target.kind == TargetKind::Dynamiclib {
+                link.push("-shared".to_string());
             }
+            link.extend(object_paths.iter().map(|object| object.to_string()));
+            makefile.push_str(&format!("\t$({}_CC) {} $({}_LDFLAGS) -o $@\n\n", name, make_escape(&link.join(" ")), name));
         }
     }
-    // --- Сборка ---
-    println!("Building target: {}", target.name);
-    let mut command = Command::new(target.compiler.clone());
-    if let Some(flags) = &target.compiler_flags {
-        for flag in flags {
-            command.arg(flag);
-        }
-    }
-    if let Some(defines) = &target.defines {
-        for define in defines {
-            command.arg(format!("-D{}", define));
-        }
+}
+
+/// Экранирует `$` для make.
+fn make_escape(value: &str) -> String {
+    value.replace('$', "$$")
+}
+
+/// Флаги компиляции исходников таргета: флаги компилятора, свои, публичные и унаследованные
+/// определения и include-директории.
+fn compile_arguments(target: &TargetConfig, usage: &[UsageRequirements]) -> Vec<String> {
+    let mut args: Vec<String> = target.compiler_flags.clone().unwrap_or_default();
+    let inherited_defines = usage.iter().flat_map(|dep_usage| dep_usage.defines.iter());
+    for define in target.defines.iter().flatten().chain(target.public_defines.iter().flatten()).chain(inherited_defines) {
+        args.push(format!("-D{}", define));
     }
-    if let Some(includes) = &target.includes {
-        for include in includes {
-            command.arg("-I").arg(include);
-        }
+    let inherited_includes = usage.iter().flat_map(|dep_usage| dep_usage.includes.iter());
+    for include in target.includes.iter().flatten().chain(target.public_includes.iter().flatten()).chain(inherited_includes) {
+        args.push("-I".to_string());
+        args.push(include.clone());
     }
-    for source in &target.sources {
-        command.arg(source);
+    if target.kind == TargetKind::Dynamiclib {
+        args.push("-fPIC".to_string());
     }
-    // Добавляем MacOS frameworks
-    if target.os_target.to_lowercase() == "macos" {
-        if let Some(frameworks) = &target.frameworks {
-            for fw in frameworks {
-                command.arg("-framework").arg(fw);
-            }
+    args
+}
+
+/// Аргументы линковки после объектных файлов. Библиотеки идут после объектов, зависимые
+/// раньше своих зависимостей, чтобы линкер разрешил символы; затем frameworks и linker_flags.
+fn link_arguments(target: &TargetConfig, usage: &[UsageRequirements]) -> Vec<String> {
+    let mut args: Vec<String> = target.public_link_libraries.iter().flatten().map(|lib| usage::link_argument(lib)).collect();
+    args.extend(usage.iter().flat_map(|dep_usage| dep_usage.libs.iter().cloned()));
+    if target.os_target == OsTarget::Macos {
+        for fw in target.frameworks.iter().flatten() {
+            args.push("-framework".to_string());
+            args.push(fw.clone());
         }
     }
-    match fs::create_dir(target.out_dir.clone()) {
-        Ok(()) => println!("Directory created successfully"),
-        Err(e) => {
-            if e.kind() == std::io::ErrorKind::AlreadyExists {
-                println!("Directory already exists");
-            } else {
-                eprintln!("Error creating directory: {}", e);
-            }
-        }
-    }
-    let output = match target.kind.as_str() {
-        "executable" => format!("{}/{}", target.out_dir, target.name),
-        "staticlib" => format!("{}/lib{}.a", target.out_dir ,target.name),
-        "dynamiclib" => format!("{}/lib{}.so", target.out_dir, target.name),
-        _ => anyhow::bail!("Unknown target kind: {}", target.kind),
-    };
-    command.arg("-o").arg(&output);
-    
-    // Печатаем сгенерированную команду
-    println!("[build] Generated command: {:?}", command);
-    
-    let status = command.status()?;
-    if !status.success() {
-        anyhow::bail!("Failed to build target: {}", target.name);
-    }
-    println!("Successfully built: {}", output);
-    // --- post_build_scripts ---
-    if let Some(scripts) = &target.post_build_scripts {
-        for script in scripts {
-            println!("Running post-build script: {}", script);
-            let status = Command::new("sh")
-                .arg("-c")
-                .arg(script)
-                .status()?;
-            if !status.success() {
-                anyhow::bail!("Post-build script failed: {}", script);
-            }
-        }
+    args.extend(target.linker_flags.iter().flatten().cloned());
+    args
+}
+
+/// Генерирует исходник с ресурсами таргета и добавляет его в sources, а заголовок — в includes.
+fn add_resources(target: &mut TargetConfig, dry_run: bool) -> Result<()> {
+    if let Some(generated) = resources::generate(target, dry_run)? {
+        target.sources.push(generated.source);
+        target.includes.get_or_insert_with(Vec::new).push(generated.include_dir);
     }
-    let mut cache_file = File::create(&cache_file_path)?;
-    writeln!(cache_file, "{}", hash)?;
     Ok(())
 }
 
-fn fetch_git_dependency_static(dep: crate::config::Dependency, force_rebuild: bool) -> anyhow::Result<()> {
-    use std::process::Command;
-    use std::path::Path;
-    let dep_dir = format!("deps/{}", dep.name);
-    let dep_path = Path::new(&dep_dir);
-    if force_rebuild && dep_path.exists() {
-        println!("Force rebuilding dependency: {}...", dep.name);
-        std::fs::remove_dir_all(&dep_dir)?;
-    }
-    if dep_path.exists() {
-        if dep_path.join(".git").exists() {
-            println!("Dependency {} already exists, pulling latest changes...", dep.name);
-            let status = Command::new("git")
-                .current_dir(&dep_dir)
-                .arg("pull")
-                .status()?;
-            if !status.success() {
-                anyhow::bail!("Failed to update dependency: {}", dep.name);
-            }
-        } else {
-            anyhow::bail!(
-                "Dependency directory '{}' exists but is not a git repository. Please remove it manually or specify a different location.",
-                dep_dir
-            );
+/// Расширения объектных файлов и библиотек, которые сборка оставляет в дереве исходников.
+const BUILD_PRODUCT_EXTENSIONS: &[&str] = &["o", "obj", "a", "lib", "so", "dylib", "dll", "lo", "la"];
+
+fn is_build_product(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| BUILD_PRODUCT_EXTENSIONS.iter().any(|known| extension == *known))
+}
+
+/// Рекурсивно хеширует пути и содержимое файлов директории в стабильном порядке.
+/// Скрытые файлы и директории (.git, кеши сборки) и файлы, для которых `skip` вернул true, пропускаются.
+fn hash_directory(dir: &Path, hasher: &mut DefaultHasher, skip: &dyn Fn(&Path) -> bool) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
         }
-    } else {
-        std::fs::create_dir_all("deps")?;
-        println!("Cloning {} from {}...", dep.name, dep.location);
-        let status = Command::new("git")
-            .arg("clone")
-            .arg(&dep.location)
-            .arg(&dep_dir)
-            .status()?;
-        if !status.success() {
-            anyhow::bail!("Failed to clone dependency: {}", dep.name);
+        let path = entry.path();
+        // Ссылка хешируется по тому, куда она указывает, как её копирует vendor::copy_dir
+        if entry.file_type()?.is_symlink() {
+            if !skip(&path) {
+                path.to_string_lossy().hash(hasher);
+                fs::read_link(&path)?.hash(hasher);
+            }
+        } else if path.is_dir() {
+            hash_directory(&path, hasher, skip)?;
+        } else if !skip(&path) {
+            path.to_string_lossy().hash(hasher);
+            fs::read(&path)?.hash(hasher);
         }
     }
     Ok(())
 }
-
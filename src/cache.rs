@@ -0,0 +1,134 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+/// Ключ кеша таргета: хеш каждой составляющей по её имени (`source src/main.c`,
+/// `header src/util.h`, `compiler_flags`, `env`...). Общий хеш считается по всем составляющим.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TargetInputs {
+    pub components: BTreeMap<String, u64>,
+}
+
+impl TargetInputs {
+    pub fn add(&mut self, name: impl Into<String>, value: impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        self.components.insert(name.into(), hasher.finish());
+    }
+
+    pub fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.components.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Читает файл кеша: общий хеш в первой строке, затем `<хеш> <составляющая>` на строку.
+    /// Файлы старого формата содержат только общий хеш, составляющих у них нет.
+    pub fn read(path: &Path) -> Option<(u64, Self)> {
+        let content = fs::read_to_string(path).ok()?;
+        let mut lines = content.lines();
+        let hash = lines.next()?.trim().parse().ok()?;
+        let components = lines
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(hash, name)| Some((name.to_string(), hash.parse().ok()?)))
+            .collect();
+        Some((hash, Self { components }))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut content = format!("{}\n", self.hash());
+        for (name, hash) in &self.components {
+            writeln!(content, "{} {}", hash, name)?;
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Составляющие, которые отличаются от прошлой сборки: изменённые, новые и пропавшие.
+    pub fn changes(&self, previous: &Self) -> Vec<String> {
+        let mut changes = vec![];
+        for (name, hash) in &self.components {
+            match previous.components.get(name) {
+                Some(old) if old != hash => changes.push(format!("changed: {}", name)),
+                None => changes.push(format!("added: {}", name)),
+                _ => {},
+            }
+        }
+        for name in previous.components.keys().filter(|name| !self.components.contains_key(*name)) {
+            changes.push(format!("removed: {}", name));
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPONENTS: &[&str] = &[
+        "source src/main.c",
+        "header src/util.h",
+        "compiler",
+        "defines",
+        "compiler_flags",
+        "includes",
+        "linker_flags",
+        "dependency zlib",
+        "usage zlib",
+        "options",
+        "env",
+        "working_dir",
+        "output",
+        "extra_steps",
+    ];
+
+    fn inputs(changed: Option<&str>) -> TargetInputs {
+        let mut inputs = TargetInputs::default();
+        for name in COMPONENTS {
+            inputs.add(*name, if Some(*name) == changed { "new" } else { "old" });
+        }
+        inputs
+    }
+
+    #[test]
+    fn reports_each_changed_component() {
+        let previous = inputs(None);
+        assert!(inputs(None).changes(&previous).is_empty());
+        assert_eq!(inputs(None).hash(), previous.hash());
+        for name in COMPONENTS {
+            let current = inputs(Some(name));
+            assert_eq!(current.changes(&previous), vec![format!("changed: {}", name)]);
+            assert_ne!(current.hash(), previous.hash(), "{} is not part of the hash", name);
+        }
+    }
+
+    #[test]
+    fn reports_added_and_removed_components() {
+        let previous = inputs(None);
+        let mut current = previous.clone();
+        current.components.remove("header src/util.h");
+        current.add("source src/extra.c", "new");
+        assert_eq!(current.changes(&previous), ["added: source src/extra.c", "removed: header src/util.h"]);
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let dir = std::env::temp_dir().join(format!("constructor-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(".build_cache_app.txt");
+        let inputs = inputs(None);
+        inputs.write(&path).unwrap();
+        assert_eq!(TargetInputs::read(&path), Some((inputs.hash(), inputs.clone())));
+
+        // Старый формат: только общий хеш, поэтому изменилось всё
+        fs::write(&path, "12345\n").unwrap();
+        let (hash, previous) = TargetInputs::read(&path).unwrap();
+        assert_eq!(hash, 12345);
+        assert_eq!(inputs.changes(&previous).len(), COMPONENTS.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use crate::config::{BuildConfig, Check, OptionValue};
+use crate::usage::link_argument;
+use anyhow::Result;
+use log::info;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Префикс, под которым результаты проверок доступны в условиях `cfg(...)`.
+pub const PREFIX: &str = "checks.";
+
+/// Файл в корне WORKSPACE с результатами проверок: `<ключ> <значение>` на строку.
+pub const CHECKS_CACHE_FILE: &str = ".constructor-checks";
+
+/// Размеры, которые перебираются для sizeof.
+const SIZES: &[i64] = &[1, 2, 4, 8, 16, 32, 64];
+
+/// Выполняет все `[[checks]]`. Результат header, function и compiler_flag — bool,
+/// sizeof — размер в байтах (0, если тип не компилируется).
+/// Компилятор запускается с тем же `env`, что и при сборке таргета проверки. Результаты кешируются
+/// по определению проверки, компилятору, флагам, этому окружению и CC/CFLAGS.
+/// С `dry_run` компилятор не запускается: для проверок без кеша берётся неуспешный результат.
+pub fn run_all(config: &BuildConfig, force: bool, dry_run: bool) -> Result<BTreeMap<String, OptionValue>> {
+    let mut values = BTreeMap::new();
+    let Some(checks) = config.checks.as_ref().filter(|checks| !checks.is_empty()) else {
+        return Ok(values);
+    };
+    let mut cache = if force { HashMap::new() } else { read_cache(Path::new(CHECKS_CACHE_FILE)) };
+    let cpp = is_cpp(&config.project.language);
+    for check in checks {
+        let target = match &check.target {
+            Some(name) => config.targets.iter().find(|target| &target.name == name),
+            None => config.targets.iter().find(|target| target.enabled != Some(false)),
+        };
+        let compiler = target.map_or("cc", |target| target.compiler.as_str());
+        let flags = target.and_then(|target| target.compiler_flags.clone()).unwrap_or_default();
+        let env = config.env_for(target);
+
+        let mut hasher = DefaultHasher::new();
+        check.hash(&mut hasher);
+        compiler.hash(&mut hasher);
+        flags.hash(&mut hasher);
+        cpp.hash(&mut hasher);
+        env.hash(&mut hasher);
+        for var in ["CC", "CXX", "CFLAGS", "CXXFLAGS", "LDFLAGS"] {
+            std::env::var(var).ok().hash(&mut hasher);
+        }
+        let key = format!("{:016x}", hasher.finish());
+
+        let value = match cache.get(&key) {
+            Some(value) => {
+                info!(stage = "checks"; "{} ({}): {} (cached)", check.name, check.subject(), value);
+                value.clone()
+            },
+            None if dry_run => {
+                let value = if check.sizeof.is_some() { OptionValue::Int(0) } else { OptionValue::Bool(false) };
+                info!(stage = "checks"; "[dry-run] {} ({}) would run, assuming {}", check.name, check.subject(), value);
+                value
+            },
+            None => {
+                let value = run(check, compiler, &flags, &env, cpp)?;
+                info!(stage = "checks"; "{} ({}): {}", check.name, check.subject(), value);
+                cache.insert(key, value.clone());
+                value
+            },
+        };
+        values.insert(check.name.clone(), value);
+    }
+    if !dry_run {
+        write_cache(Path::new(CHECKS_CACHE_FILE), &cache)?;
+    }
+    Ok(values)
+}
+
+/// Дефайны проверок с `define = true` для таргетов с `project_defines = true`.
+/// Неуспешные проверки не определяются вовсе, как в autoconf.
+pub fn defines(config: &BuildConfig, values: &BTreeMap<String, OptionValue>) -> Vec<String> {
+    let mut defines = vec![];
+    for check in config.checks.iter().flatten().filter(|check| check.define == Some(true)) {
+        match values.get(&check.name) {
+            Some(OptionValue::Bool(true)) => defines.push(format!("{}=1", check.name)),
+            Some(OptionValue::Int(size)) if *size > 0 => defines.push(format!("{}={}", check.name, size)),
+            _ => {},
+        }
+    }
+    defines
+}
+
+fn run(check: &Check, compiler: &str, flags: &[String], env: &[(String, String)], cpp: bool) -> Result<OptionValue> {
+    let includes: String = check
+        .includes
+        .iter()
+        .flatten()
+        .map(|header| format!("#include <{}>\n", header))
+        .collect();
+    if let Some(header) = &check.header {
+        let source = format!("{}#include <{}>\nint main(void) {{ return 0; }}\n", includes, header);
+        return Ok(OptionValue::Bool(try_compile(compiler, flags, env, &source, cpp, false, &[])?));
+    }
+    if let Some(function) = &check.function {
+        // Как в autoconf: объявляем функцию сами, чтобы проверить именно линковку
+        let source = format!(
+            "#ifdef __cplusplus\nextern \"C\"\n#endif\nchar {}(void);\nint main(void) {{ return (int) {}(); }}\n",
+            function, function
+        );
+        let libs: Vec<String> = check.libs.iter().flatten().map(|lib| link_argument(lib)).collect();
+        return Ok(OptionValue::Bool(try_compile(compiler, flags, env, &source, cpp, true, &libs)?));
+    }
+    if let Some(type_name) = &check.sizeof {
+        // Размер выясняется только компиляцией, поэтому работает и при кросс-компиляции
+        let includes = if includes.is_empty() { "#include <stddef.h>\n#include <stdint.h>\n".to_string() } else { includes };
+        for size in SIZES {
+            let source = format!(
+                "{}typedef char constructor_check[(sizeof({}) == {}) ? 1 : -1];\nint main(void) {{ return 0; }}\n",
+                includes, type_name, size
+            );
+            if try_compile(compiler, flags, env, &source, cpp, false, &[])? {
+                return Ok(OptionValue::Int(*size));
+            }
+        }
+        return Ok(OptionValue::Int(0));
+    }
+    if let Some(flag) = &check.compiler_flag {
+        // -Werror, чтобы флаг, о котором компилятор только предупреждает, считался неподдерживаемым
+        let source = "int main(void) { return 0; }\n";
+        let flags: Vec<String> = flags.iter().cloned().chain([flag.clone(), "-Werror".to_string()]).collect();
+        return Ok(OptionValue::Bool(try_compile(compiler, &flags, env, source, cpp, false, &[])?));
+    }
+    anyhow::bail!("check '{}' has nothing to check", check.name)
+}
+
+/// Компилирует (и при `link` линкует) тестовую программу во временной директории.
+fn try_compile(
+    compiler: &str,
+    flags: &[String],
+    env: &[(String, String)],
+    source: &str,
+    cpp: bool,
+    link: bool,
+    libs: &[String],
+) -> Result<bool> {
+    let dir = scratch_dir()?;
+    let source_path = dir.join(if cpp { "check.cpp" } else { "check.c" });
+    fs::write(&source_path, source)?;
+    let mut command = Command::new(compiler);
+    command.envs(env.iter().cloned()).args(flags);
+    if !link {
+        command.arg("-c");
+    }
+    command
+        .arg(&source_path)
+        .arg("-o")
+        .arg(dir.join(if link { "check" } else { "check.o" }))
+        .args(libs)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let status = command.status();
+    let _ = fs::remove_dir_all(&dir);
+    match status {
+        Ok(status) => Ok(status.success()),
+        Err(e) => Err(anyhow::anyhow!("Failed to run compiler '{}' for checks: {}", compiler, e)),
+    }
+}
+
+fn scratch_dir() -> Result<PathBuf> {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let index = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("constructor-check-{}-{}", std::process::id(), index));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn is_cpp(language: &str) -> bool {
+    matches!(language.to_lowercase().as_str(), "c++" | "cpp" | "cxx")
+}
+
+fn read_cache(path: &Path) -> HashMap<String, OptionValue> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(key, value)| {
+            let value = match value {
+                "true" => OptionValue::Bool(true),
+                "false" => OptionValue::Bool(false),
+                value => OptionValue::Int(value.parse().ok()?),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn write_cache(path: &Path, cache: &HashMap<String, OptionValue>) -> Result<()> {
+    let mut lines: Vec<String> = cache.iter().map(|(key, value)| format!("{} {}\n", key, value)).collect();
+    lines.sort();
+    let content: String = lines.concat();
+    if fs::read_to_string(path).ok().as_deref() != Some(content.as_str()) {
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_round_trips() {
+        let dir = std::env::temp_dir().join(format!("constructor-checks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CHECKS_CACHE_FILE);
+        assert!(read_cache(&path).is_empty());
+
+        let cache = HashMap::from([
+            ("00000000000000aa".to_string(), OptionValue::Bool(true)),
+            ("00000000000000bb".to_string(), OptionValue::Bool(false)),
+            ("00000000000000cc".to_string(), OptionValue::Int(8)),
+        ]);
+        write_cache(&path, &cache).unwrap();
+        // Строки отсортированы, чтобы файл не менялся от порядка в HashMap
+        assert_eq!(fs::read_to_string(&path).unwrap(), "00000000000000aa true\n00000000000000bb false\n00000000000000cc 8\n");
+        assert_eq!(read_cache(&path), cache);
+
+        // Испорченные строки пропускаются
+        fs::write(&path, "00000000000000aa true\ngarbage\n00000000000000dd maybe\n").unwrap();
+        assert_eq!(read_cache(&path), HashMap::from([("00000000000000aa".to_string(), OptionValue::Bool(true))]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn defines_only_successful_checks() {
+        let config: BuildConfig = toml::from_str(
+            r#"
+            [project]
+            name = "demo"
+            version = "1.0"
+            language = "C"
+
+            [[checks]]
+            name = "HAVE_UNISTD_H"
+            header = "unistd.h"
+            define = true
+
+            [[checks]]
+            name = "HAVE_FOO_H"
+            header = "foo.h"
+            define = true
+
+            [[checks]]
+            name = "SIZEOF_LONG"
+            sizeof = "long"
+            define = true
+
+            [[checks]]
+            name = "SIZEOF_MISSING"
+            sizeof = "struct missing"
+            define = true
+
+            [[checks]]
+            name = "HAVE_STDIO_H"
+            header = "stdio.h"
+            "#,
+        )
+        .unwrap();
+        let values = BTreeMap::from([
+            ("HAVE_UNISTD_H".to_string(), OptionValue::Bool(true)),
+            ("HAVE_FOO_H".to_string(), OptionValue::Bool(false)),
+            ("SIZEOF_LONG".to_string(), OptionValue::Int(8)),
+            ("SIZEOF_MISSING".to_string(), OptionValue::Int(0)),
+            ("HAVE_STDIO_H".to_string(), OptionValue::Bool(true)),
+        ]);
+        assert_eq!(defines(&config, &values), ["HAVE_UNISTD_H=1", "SIZEOF_LONG=8"]);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::{BuildConfig, CustomCommand};
+use crate::logging::{self, Prefix};
+use anyhow::Result;
+use log::info;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// Файл в корне WORKSPACE с хешами выполненных `[[commands]]`: `<имя> <хеш>` на строку.
+pub const COMMANDS_CACHE_FILE: &str = ".constructor-commands";
+
+/// Выполняет `[[commands]]` в порядке объявления. Команда запускается, если изменилось её
+/// определение, окружение или список входов, если какого-то выхода нет или если какой-то
+/// вход новее самого старого выхода. С `dry_run` команды только печатаются; команда, входы которой
+/// генерирует такая пропущенная команда, тоже считается устаревшей.
+pub fn run_all(config: &BuildConfig, force: bool, dry_run: bool) -> Result<()> {
+    let Some(commands) = config.commands.as_ref().filter(|commands| !commands.is_empty()) else {
+        return Ok(());
+    };
+    let env = config.env.clone().unwrap_or_default();
+    let mut cache = if force { HashMap::new() } else { read_cache() };
+    // Выходы команд, которые в dry-run не запускались: их может ещё не быть
+    let mut pending: HashSet<&str> = HashSet::new();
+    for command in commands {
+        let (inputs, waits_for_pending) = inputs(command, &pending)?;
+        let mut hasher = DefaultHasher::new();
+        command.hash(&mut hasher);
+        env.hash(&mut hasher);
+        inputs.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if waits_for_pending {
+            info!(stage = "commands"; "{} is stale: its inputs are generated by earlier commands", command.name);
+        } else if cache.get(&command.name) == Some(&hash) && is_up_to_date(&inputs, &command.outputs) {
+            info!(stage = "commands"; "{} is up to date", command.name);
+            continue;
+        }
+        info!(stage = "commands"; "{}: {}", command.name, command.description.as_deref().unwrap_or(&command.run));
+        if dry_run {
+            info!(stage = "commands"; "[dry-run] sh -c {:?}", command.run);
+            pending.extend(command.outputs.iter().map(String::as_str));
+            continue;
+        }
+        for output in &command.outputs {
+            if let Some(parent) = Path::new(output).parent()
+                && !parent.as_os_str().is_empty()
+            {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let mut child = Command::new("sh");
+        child.arg("-c").arg(&command.run).envs(env.iter().cloned());
+        let status = logging::run_command(&mut child, Prefix::Stage("commands"))
+            .map_err(|e| anyhow::anyhow!("Failed to run command '{}': {}", command.name, e))?;
+        if !status.success() {
+            cache.remove(&command.name);
+            write_cache(&cache)?;
+            anyhow::bail!("Command '{}' failed ({}): {}", command.name, status, command.run);
+        }
+        if let Some(missing) = command.outputs.iter().find(|output| !Path::new(output).exists()) {
+            anyhow::bail!("Command '{}' did not produce declared output {}", command.name, missing);
+        }
+        cache.insert(command.name.clone(), hash);
+    }
+    if dry_run {
+        return Ok(());
+    }
+    write_cache(&cache)
+}
+
+/// Удаляет выходы команд и файл с их хешами.
+pub fn clean(config: &BuildConfig) -> Result<()> {
+    for output in config.commands.iter().flatten().flat_map(|command| &command.outputs) {
+        if Path::new(output).is_file() {
+            info!(stage = "commands"; "Removing generated file {}", output);
+            fs::remove_file(output)?;
+        }
+    }
+    if Path::new(COMMANDS_CACHE_FILE).exists() {
+        info!(stage = "commands"; "Removing cache file {}", COMMANDS_CACHE_FILE);
+        fs::remove_file(COMMANDS_CACHE_FILE)?;
+    }
+    Ok(())
+}
+
+/// Входы команды: пути как есть, glob-шаблоны раскрываются в алфавитном порядке.
+/// Второе значение — есть ли среди входов выходы из `pending`, которые ещё не сгенерированы.
+fn inputs(command: &CustomCommand, pending: &HashSet<&str>) -> Result<(Vec<PathBuf>, bool)> {
+    let mut inputs = vec![];
+    let mut waits_for_pending = false;
+    for pattern in command.inputs.iter().flatten() {
+        if !pattern.contains(['*', '?', '[']) {
+            if pending.contains(pattern.as_str()) {
+                waits_for_pending = true;
+            } else if !Path::new(pattern).exists() {
+                anyhow::bail!("Command '{}': input {} does not exist", command.name, pattern);
+            }
+            inputs.push(PathBuf::from(pattern));
+            continue;
+        }
+        let mut matched = glob::glob(pattern)
+            .map_err(|e| anyhow::anyhow!("Command '{}': invalid input pattern '{}': {}", command.name, pattern, e))?
+            .collect::<Result<Vec<_>, _>>()?;
+        matched.sort();
+        inputs.extend(matched);
+    }
+    Ok((inputs, waits_for_pending))
+}
+
+fn is_up_to_date(inputs: &[PathBuf], outputs: &[String]) -> bool {
+    let oldest_output = outputs.iter().map(|output| modified(Path::new(output))).min().flatten();
+    let Some(oldest_output) = oldest_output else {
+        return false;
+    };
+    inputs.iter().all(|input| modified(input).is_some_and(|time| time <= oldest_output))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn read_cache() -> HashMap<String, u64> {
+    let Ok(content) = fs::read_to_string(COMMANDS_CACHE_FILE) else {
+        return HashMap::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(name, hash)| Some((name.to_string(), hash.parse().ok()?)))
+        .collect()
+}
+
+fn write_cache(cache: &HashMap<String, u64>) -> Result<()> {
+    let mut lines: Vec<String> = cache.iter().map(|(name, hash)| format!("{} {}\n", name, hash)).collect();
+    lines.sort();
+    let content: String = lines.concat();
+    if fs::read_to_string(COMMANDS_CACHE_FILE).ok().as_deref() != Some(content.as_str()) {
+        fs::write(COMMANDS_CACHE_FILE, content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("constructor-commands-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Создаёт файл с заданным временем изменения (в секундах от эпохи).
+    fn touch(path: &Path, seconds: u64) {
+        let file = File::create(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    fn command(inputs: &[&Path]) -> CustomCommand {
+        CustomCommand {
+            name: "gen".to_string(),
+            run: "true".to_string(),
+            inputs: Some(inputs.iter().map(|input| input.to_string_lossy().to_string()).collect()),
+            outputs: vec![],
+            description: None,
+        }
+    }
+
+    #[test]
+    fn compares_input_and_output_times() {
+        let dir = temp_dir("mtime");
+        let (input, first, second) = (dir.join("in.proto"), dir.join("a.c"), dir.join("a.h"));
+        let outputs = [first.to_string_lossy().to_string(), second.to_string_lossy().to_string()];
+        touch(&input, 1_000);
+        assert!(!is_up_to_date(std::slice::from_ref(&input), &outputs), "outputs are missing");
+
+        touch(&first, 2_000);
+        touch(&second, 3_000);
+        assert!(is_up_to_date(std::slice::from_ref(&input), &outputs));
+        // Вход сравнивается с самым старым выходом
+        touch(&input, 2_500);
+        assert!(!is_up_to_date(std::slice::from_ref(&input), &outputs));
+        touch(&input, 2_000);
+        assert!(is_up_to_date(std::slice::from_ref(&input), &outputs));
+        // Пропавший вход тоже делает команду устаревшей
+        assert!(!is_up_to_date(&[dir.join("gone.proto")], &outputs));
+        assert!(is_up_to_date(&[], &outputs));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expands_inputs() {
+        let dir = temp_dir("inputs");
+        for name in ["b.proto", "a.proto", "notes.txt"] {
+            touch(&dir.join(name), 1_000);
+        }
+        let plain = dir.join("notes.txt");
+        let pattern = dir.join("*.proto");
+        let (inputs, waits) = inputs(&command(&[&plain, &pattern]), &HashSet::new()).unwrap();
+        assert_eq!(inputs, [plain.clone(), dir.join("a.proto"), dir.join("b.proto")]);
+        assert!(!waits);
+
+        let missing = dir.join("gen/schema.json");
+        let error = super::inputs(&command(&[&missing]), &HashSet::new()).unwrap_err();
+        assert_eq!(error.to_string(), format!("Command 'gen': input {} does not exist", missing.display()));
+        // Вход, который сгенерирует пропущенная в dry-run команда, ещё может не существовать
+        let pending = missing.to_string_lossy().to_string();
+        let (inputs, waits) = super::inputs(&command(&[&missing]), &HashSet::from([pending.as_str()])).unwrap();
+        assert_eq!(inputs, [missing]);
+        assert!(waits);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,18 +1,138 @@
+use crate::convert::Format;
+use crate::{interpolate, merge, options, platform};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    Executable,
+    Staticlib,
+    Dynamiclib,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum OsTarget {
+    Linux,
+    Macos,
+    Windows,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencySource {
+    Git,
+    Local,
+    System,
+    Archive,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    Cmake,
+    Make,
+    Autotools,
+    Constructor,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionType {
+    Bool,
+    Int,
+    String,
+}
+
+/// Значение опции сборки в конфиге: `default = false`, `default = 4`, `default = "openssl"`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TargetKind::Executable => "executable",
+            TargetKind::Staticlib => "staticlib",
+            TargetKind::Dynamiclib => "dynamiclib",
+        })
+    }
+}
+
+impl fmt::Display for OsTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OsTarget::Linux => "linux",
+            OsTarget::Macos => "macos",
+            OsTarget::Windows => "windows",
+        })
+    }
+}
+
+impl fmt::Display for DependencySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DependencySource::Git => "git",
+            DependencySource::Local => "local",
+            DependencySource::System => "system",
+            DependencySource::Archive => "archive",
+        })
+    }
+}
+
+impl fmt::Display for OptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OptionType::Bool => "bool",
+            OptionType::Int => "int",
+            OptionType::String => "string",
+        })
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Int(value) => write!(f, "{}", value),
+            OptionValue::String(value) => f.write_str(value),
+        }
+    }
+}
+
+impl fmt::Display for BuildSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BuildSystem::Cmake => "cmake",
+            BuildSystem::Make => "make",
+            BuildSystem::Autotools => "autotools",
+            BuildSystem::Constructor => "constructor",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TargetConfig {
     pub name: String,
     pub out_dir: String,
-    pub kind: String,
+    pub kind: TargetKind,
     pub sources: Vec<String>,
     pub includes: Option<Vec<String>>,
     pub defines: Option<Vec<String>>,
     pub linker_flags: Option<Vec<String>>,
     pub compiler_flags: Option<Vec<String>>,
     pub frameworks: Option<Vec<String>>, // MacOS frameworks
-    pub os_target: String,
+    pub os_target: OsTarget,
     pub compiler: String,
     pub pre_build_scripts: Option<Vec<String>>, // скрипты до сборки
     pub post_build_scripts: Option<Vec<String>>, // скрипты после сборки
@@ -22,42 +142,518 @@ pub struct TargetConfig {
     pub extra_steps: Option<Vec<String>>,
     pub enabled: Option<bool>,
     pub description: Option<String>,
+    pub deps: Option<Vec<String>>, // зависимости и библиотечные таргеты; по умолчанию все [[dependencies]]
+    pub public_includes: Option<Vec<String>>, // наследуются всеми, кто зависит от таргета
+    pub public_defines: Option<Vec<String>>,
+    pub public_link_libraries: Option<Vec<String>>,
+    pub when: Option<BTreeMap<String, TargetConditional>>, // "linux" или "cfg(target_os = \"linux\", ...)"
+    pub resources: Option<Vec<String>>, // файлы или glob-шаблоны, встраиваемые как массивы байт
+    pub project_defines: Option<bool>, // передавать опции и проверки с define = true как -D; по умолчанию только через configure_files
+}
+
+/// Что добавляется к таргету, если условие из `when` выполняется для платформы его тулчейна.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConditional
+{
+    pub sources: Option<Vec<String>>,
+    pub includes: Option<Vec<String>>,
+    pub defines: Option<Vec<String>>,
+    pub compiler_flags: Option<Vec<String>>,
+    pub linker_flags: Option<Vec<String>>,
+    pub link_libraries: Option<Vec<String>>, // "m" превращается в -lm
+    pub frameworks: Option<Vec<String>>,
+}
+
+impl TargetConfig {
+    /// Путь к итоговому артефакту таргета (исполняемый файл или библиотека):
+    /// `custom_output`, если он задан, иначе файл в out_dir.
+    pub fn output_path(&self) -> String {
+        if let Some(custom_output) = &self.custom_output {
+            return custom_output.clone();
+        }
+        match self.kind {
+            TargetKind::Executable => format!("{}/{}", self.out_dir, self.name),
+            TargetKind::Staticlib => format!("{}/lib{}.a", self.out_dir, self.name),
+            TargetKind::Dynamiclib => format!("{}/lib{}.so", self.out_dir, self.name),
+        }
+    }
+
+    pub fn is_library(&self) -> bool {
+        matches!(self.kind, TargetKind::Staticlib | TargetKind::Dynamiclib)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct BuildConfig
 {
+    #[serde(rename = "$schema")]
+    pub schema: Option<String>, // путь или URL JSON Schema, которую подхватывает редактор
     pub project: ProjectConfig,
     pub dependencies: Option<Vec<Dependency>>,
-    pub targets: Vec<TargetConfig>,
+    #[serde(default)]
+    pub targets: Vec<TargetConfig>, // могут целиком приходить из include
     pub description: Option<String>,
     pub env: Option<Vec<(String, String)>>,
+    pub vars: Option<BTreeMap<String, String>>, // пользовательские переменные для ${...}
+    pub include: Option<Vec<String>>, // glob-шаблоны файлов с таргетами и зависимостями
+    pub options: Option<BTreeMap<String, BuildOption>>, // опции сборки, меняются через --set
+    pub configure_files: Option<Vec<ConfigureFile>>, // шаблоны с @VAR@ и #cmakedefine
+    pub checks: Option<Vec<Check>>, // проверки возможностей тулчейна перед сборкой
+    pub commands: Option<Vec<CustomCommand>>, // генераторы файлов с объявленными входами и выходами
+    /// Итоговые значения опций (default с учётом --set), заполняются при загрузке
+    #[serde(skip)]
+    pub option_values: BTreeMap<String, OptionValue>,
+}
+
+impl BuildConfig {
+    /// Переменные окружения для команд: сначала `env` проекта, затем `env` таргета, если он есть,
+    /// так что таргет переопределяет одноимённые переменные проекта.
+    pub fn env_for(&self, target: Option<&TargetConfig>) -> Vec<(String, String)> {
+        let target_env = target.and_then(|target| target.env.as_ref());
+        self.env.iter().flatten().chain(target_env.into_iter().flatten()).cloned().collect()
+    }
+}
+
+/// Шаблон, из которого перед сборкой генерируется файл (обычно заголовок с версией и опциями).
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigureFile
+{
+    pub input: String, // например "src/config.h.in"
+    pub output: String, // например "build/generated/config.h"
+}
+
+/// Проверка возможности тулчейна: задаётся ровно одно из header, function, sizeof, compiler_flag.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Check
+{
+    pub name: String, // например "HAVE_UNISTD_H"
+    pub header: Option<String>, // заголовок, который должен компилироваться
+    pub function: Option<String>, // функция, которая должна линковаться
+    pub sizeof: Option<String>, // тип, размер которого нужно узнать
+    pub compiler_flag: Option<String>, // флаг, который должен приниматься компилятором
+    pub includes: Option<Vec<String>>, // заголовки для function и sizeof
+    pub libs: Option<Vec<String>>, // библиотеки для function: "m" превращается в -lm
+    pub target: Option<String>, // чьи compiler и compiler_flags использовать; по умолчанию первого таргета
+    pub define: Option<bool>, // передать результат всем таргетам через -D
+}
+
+impl Check {
+    /// Что именно проверяется, для сообщений: "header unistd.h".
+    pub fn subject(&self) -> String {
+        let subjects = [
+            ("header", &self.header),
+            ("function", &self.function),
+            ("sizeof", &self.sizeof),
+            ("compiler flag", &self.compiler_flag),
+        ];
+        subjects
+            .iter()
+            .filter_map(|(kind, value)| value.as_ref().map(|value| format!("{} {}", kind, value)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Опция сборки из секции `[options]`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BuildOption
+{
+    #[serde(rename = "type")]
+    pub kind: OptionType,
+    pub default: OptionValue,
+    pub description: Option<String>,
+    pub choices: Option<Vec<String>>, // допустимые значения для type = "string"
+    pub define: Option<String>, // имя дефайна; по умолчанию имя опции в верхнем регистре
+}
+
+/// Команда, которая генерирует файлы (protobuf, flex/bison, штамп версии). Выполняется перед
+/// сборкой таргетов, только если изменились входы или сама команда; выходы можно указывать
+/// в sources таргетов.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCommand
+{
+    pub name: String,
+    pub run: String, // выполняется через sh -c из корня WORKSPACE с env проекта
+    pub inputs: Option<Vec<String>>, // файлы или glob-шаблоны
+    pub outputs: Vec<String>,
+    pub description: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Файл, подключаемый через `include`: только таргеты, зависимости, команды и свои include.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IncludeFile
+{
+    pub dependencies: Option<Vec<Dependency>>,
+    #[serde(default)]
+    pub targets: Vec<TargetConfig>,
+    pub commands: Option<Vec<CustomCommand>>,
+    pub include: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ProjectConfig {
     pub name: String,
     pub version: String,
     pub language: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Dependency
 {
     pub name: String,
-    pub source: String,
-    pub location: String,
+    pub source: DependencySource,
+    pub location: Option<String>, // git URL или путь для source = "local"
+    pub rev: Option<String>, // коммит, тег или ветка для source = "git"
+    pub url: Option<String>, // для source = "archive", поддерживается file://
+    pub sha256: Option<String>, // обязателен для source = "archive"
+    pub strip_prefix: Option<String>, // поддиректория архива, которая станет корнем зависимости
+    // Всё ниже — публичные требования, которые наследуют потребители зависимости
+    pub includes: Option<Vec<String>>, // include-директории относительно корня зависимости
+    pub defines: Option<Vec<String>>,
+    pub libs: Option<Vec<String>>, // готовые библиотеки относительно корня зависимости
+    pub link_libraries: Option<Vec<String>>, // системные библиотеки: "z" превращается в -lz
+    pub deps: Option<Vec<String>>, // другие зависимости, требования которых тоже наследуются
+    pub build: Option<DependencyBuild>, // как собрать зависимость перед использованием
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DependencyBuild
+{
+    pub system: BuildSystem,
+    pub options: Option<Vec<String>>, // аргументы cmake/configure/make
+    pub outputs: Option<Vec<String>>, // ожидаемые библиотеки относительно результата сборки
+}
+
+impl Dependency {
+    pub fn location(&self) -> anyhow::Result<&str> {
+        self.location.as_deref().ok_or_else(|| {
+            anyhow::anyhow!("Dependency '{}' ({}) requires 'location'", self.name, self.source)
+        })
+    }
+
+    /// Директория с исходниками зависимости после загрузки.
+    pub fn root(&self) -> anyhow::Result<PathBuf> {
+        match self.source {
+            DependencySource::Local => Ok(PathBuf::from(self.location()?)),
+            _ => Ok(Path::new("deps").join(&self.name)),
+        }
+    }
+}
+
+/// Конфиг, который создаёт `init` и который используется, если WORKSPACE не найден.
+pub const DEFAULT_WORKSPACE_FILE_NAME: &str = "WORKSPACE.constructor";
+
+/// Имена файлов, под которыми ищется WORKSPACE в текущей директории (без --config)
+/// и внутри директории зависимости.
+pub const WORKSPACE_FILE_NAMES: &[&str] = &[
+    "WORKSPACE.constructor",
+    "WORKSPACE.toml",
+    "WORKSPACE.yaml",
+    "WORKSPACE.yml",
+    "WORKSPACE.json",
+];
+
+pub fn find_workspace(dir: &Path) -> Option<PathBuf>
+{
+    WORKSPACE_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Неотслеживаемый файл рядом с WORKSPACE с локальными переопределениями (формат TOML).
+pub const LOCAL_OVERLAY_FILE_NAME: &str = "WORKSPACE.local";
+
+/// Профиль сборки по умолчанию, доступен в конфиге как `${profile}`.
+pub const DEFAULT_PROFILE: &str = "debug";
+
+/// Параметры загрузки конфига, которые задаются из командной строки.
+#[derive(Debug, Clone)]
+pub struct LoadOptions
+{
+    pub profile: String,
+    /// Файлы, которые накладываются поверх основного конфига (повторные --config)
+    pub overlays: Vec<String>,
+    /// Значения опций из --set name=value
+    pub set: Vec<(String, String)>,
 }
 
-pub fn load_config(path: &str) -> anyhow::Result<BuildConfig>
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self { profile: DEFAULT_PROFILE.to_string(), overlays: vec![], set: vec![] }
+    }
+}
+
+pub fn load_config(path: &str, load_options: &LoadOptions) -> anyhow::Result<BuildConfig>
 {
-    let content = std::fs::read_to_string(path)?;
+    let config: BuildConfig = parse_file(path)?;
+    let mut merged = merge::Merged::new(path, &config)?;
+    merged.include_all(path, config.include.as_deref().unwrap_or_default())?;
+
+    // WORKSPACE.local рядом с основным конфигом применяется первым, затем --config по порядку
+    let local = Path::new(path).with_file_name(LOCAL_OVERLAY_FILE_NAME);
+    let local = local.is_file().then(|| local.to_string_lossy().to_string());
+    for overlay in local.iter().chain(load_options.overlays.iter()) {
+        merged.apply_overlay(overlay)?;
+    }
 
-    if path.ends_with(".toml") || path.ends_with(".constructor") {
-        Ok(toml::from_str(&content)?)
-    } else if path.ends_with(".yaml") || path.ends_with(".yml") {
-        Ok(serde_yaml::from_str(&content)?)
-    } else {
-        Err(anyhow::anyhow!("Unsupported config file format!"))
+    // Директория файла, где объявлена каждая зависимость: от неё считаются локальные пути.
+    // Зависимости из оверлеев считаются от директории основного конфига
+    let dep_dirs = merged.dependency_dirs();
+    let config_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut config = merged.into_config(path)?;
+    let option_values = options::resolve(config.options.as_ref(), &load_options.set)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+    config = interpolate::interpolate(config, &load_options.profile, &option_values)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+    config.option_values = option_values;
+    for dep in config.dependencies.iter_mut().flatten() {
+        if dep.source == DependencySource::Local
+            && let Some(location) = &dep.location
+            && Path::new(location).is_relative()
+        {
+            let dir = dep_dirs.get(&dep.name).map_or(config_dir, PathBuf::as_path);
+            dep.location = Some(dir.join(location).to_string_lossy().to_string());
+        }
     }
-}
\ No newline at end of file
+    validate(&config, path)?;
+    Ok(config)
+}
+
+/// JSON Schema для WORKSPACE-файлов, построенная по `BuildConfig`.
+pub fn json_schema() -> anyhow::Result<String>
+{
+    let schema = schemars::schema_for!(BuildConfig);
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+/// Читает TOML, YAML или JSON файл в `T`, ошибки содержат файл, строку и колонку.
+pub fn parse_file<T: DeserializeOwned>(path: &str) -> anyhow::Result<T>
+{
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+    let format = Format::from_path(path)
+        .ok_or_else(|| anyhow::anyhow!("{}: unsupported config file format", path))?;
+    parse_str(path, format, &content)
+}
+
+/// Разбирает содержимое в формате `format`; `path` нужен только для сообщений об ошибках.
+pub fn parse_str<T: DeserializeOwned>(path: &str, format: Format, content: &str) -> anyhow::Result<T>
+{
+    match format {
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| {
+            let (line, column) = e
+                .location()
+                .map(|location| (location.line(), location.column()))
+                .unwrap_or((1, 1));
+            // serde_yaml сам дописывает позицию в текст ошибки, она уже есть в префиксе
+            let message = e.to_string();
+            let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+            located_error(path, line, column, message)
+        }),
+        Format::Json => serde_json::from_str(content).map_err(|e| {
+            let message = e.to_string();
+            let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+            located_error(path, e.line(), e.column(), message)
+        }),
+        Format::Toml => toml::from_str(content).map_err(|e| {
+            let (line, column) = e
+                .span()
+                .map(|span| line_column(content, span.start))
+                .unwrap_or((1, 1));
+            located_error(path, line, column, e.message())
+        }),
+    }
+}
+
+fn located_error(path: &str, line: usize, column: usize, message: &str) -> anyhow::Error {
+    anyhow::anyhow!("{}:{}:{}: {}", path, line, column, message.trim())
+}
+
+/// Номер строки и колонки (с единицы) для смещения в байтах.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |tail| tail.chars().count()) + 1;
+    (line, column)
+}
+
+/// Проверки, которые нельзя выразить типами: уникальность имён и выходных файлов,
+/// обязательные поля для конкретного вида зависимости, ссылки в deps.
+pub fn validate(config: &BuildConfig, path: &str) -> anyhow::Result<()>
+{
+    let mut errors = vec![];
+
+    let mut target_names = HashSet::new();
+    // Таргеты под разные ОС никогда не собираются вместе и могут писать в один файл
+    let mut outputs: HashMap<(String, OsTarget), &str> = HashMap::new();
+    for target in &config.targets {
+        if !target_names.insert(target.name.as_str()) {
+            errors.push(format!("duplicate target name '{}'", target.name));
+        }
+        let conditional_sources = target.when.iter().flatten().any(|(_, block)| block.sources.is_some());
+        if target.sources.is_empty() && !conditional_sources {
+            errors.push(format!("target '{}' has no sources", target.name));
+        }
+        for condition in target.when.iter().flatten().map(|(condition, _)| condition) {
+            match platform::Cfg::parse(condition) {
+                Ok(cfg) => {
+                    for reference in cfg.references() {
+                        let known = match reference.split_once('.') {
+                            Some(("options", option)) => config.option_values.contains_key(option),
+                            Some(("checks", check)) => config.checks.iter().flatten().any(|c| c.name == check),
+                            _ => false,
+                        };
+                        if !known {
+                            errors.push(format!("target '{}' has a condition on unknown '{}'", target.name, reference));
+                        }
+                    }
+                    for name in cfg.unknown_names() {
+                        errors.push(format!(
+                            "target '{}' has a condition on unknown platform '{}' (expected an OS such as linux, an arch such as aarch64 or a family such as unix)",
+                            target.name, name
+                        ));
+                    }
+                },
+                Err(e) => {
+                    errors.push(format!("target '{}' has an invalid condition '{}': {}", target.name, condition, e));
+                },
+            }
+        }
+        if let Some(false) = target.enabled { continue; }
+        let output = target.output_path();
+        if let Some(other) = outputs.insert((output.clone(), target.os_target), &target.name) {
+            errors.push(format!("targets '{}' and '{}' both produce {}", other, target.name, output));
+        }
+    }
+
+    let mut dep_names = HashSet::new();
+    for dep in config.dependencies.iter().flatten() {
+        if !dep_names.insert(dep.name.as_str()) {
+            errors.push(format!("duplicate dependency name '{}'", dep.name));
+        }
+        if target_names.contains(dep.name.as_str()) {
+            errors.push(format!("dependency '{}' has the same name as a target", dep.name));
+        }
+        match dep.source {
+            DependencySource::Git | DependencySource::Local if dep.location.is_none() => {
+                errors.push(format!("dependency '{}' ({}) requires 'location'", dep.name, dep.source));
+            },
+            DependencySource::Archive => {
+                if dep.url.is_none() {
+                    errors.push(format!("dependency '{}' (archive) requires 'url'", dep.name));
+                }
+                match &dep.sha256 {
+                    None => errors.push(format!("dependency '{}' (archive) requires 'sha256'", dep.name)),
+                    Some(sha256) if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        errors.push(format!("dependency '{}' has an invalid sha256 '{}'", dep.name, sha256));
+                    },
+                    Some(_) => {},
+                }
+            },
+            _ => {},
+        }
+        if dep.source == DependencySource::System && dep.build.is_some() {
+            errors.push(format!("dependency '{}' (system) cannot have a build section", dep.name));
+        }
+    }
+
+    let mut configured = HashSet::new();
+    for file in config.configure_files.iter().flatten() {
+        if !configured.insert(file.output.as_str()) {
+            errors.push(format!("configure file {} is generated more than once", file.output));
+        }
+    }
+
+    // Выход команды может быть входом только для команд, объявленных после неё
+    let mut command_names = HashSet::new();
+    let mut generated: HashMap<&str, &str> = HashMap::new();
+    for command in config.commands.iter().flatten() {
+        if !command_names.insert(command.name.as_str()) {
+            errors.push(format!("duplicate command name '{}'", command.name));
+        }
+        if command.outputs.is_empty() {
+            errors.push(format!("command '{}' has no outputs", command.name));
+        }
+        for input in command.inputs.iter().flatten() {
+            if command.outputs.contains(input) {
+                errors.push(format!("command '{}' uses its own output {} as an input", command.name, input));
+            }
+        }
+        for output in &command.outputs {
+            if configured.contains(output.as_str()) {
+                errors.push(format!("command '{}' output {} is also a configure file", command.name, output));
+            }
+            if let Some(other) = generated.insert(output.as_str(), command.name.as_str()) {
+                errors.push(format!("commands '{}' and '{}' both produce {}", other, command.name, output));
+            }
+        }
+    }
+    for (index, command) in config.commands.iter().flatten().enumerate() {
+        for input in command.inputs.iter().flatten() {
+            let producer = config.commands.iter().flatten().skip(index + 1).find(|later| later.outputs.contains(input));
+            if let Some(producer) = producer {
+                errors.push(format!(
+                    "command '{}' uses {} produced by '{}', which is declared after it",
+                    command.name, input, producer.name
+                ));
+            }
+        }
+    }
+
+    let mut check_names = HashSet::new();
+    for check in config.checks.iter().flatten() {
+        if !check_names.insert(check.name.as_str()) {
+            errors.push(format!("duplicate check name '{}'", check.name));
+        }
+        let kinds = [&check.header, &check.function, &check.sizeof, &check.compiler_flag];
+        if kinds.iter().filter(|kind| kind.is_some()).count() != 1 {
+            errors.push(format!(
+                "check '{}' must set exactly one of header, function, sizeof, compiler_flag",
+                check.name
+            ));
+        }
+        if let Some(target) = &check.target
+            && !target_names.contains(target.as_str())
+        {
+            errors.push(format!("check '{}' uses the toolchain of unknown target '{}'", check.name, target));
+        }
+    }
+
+    for (owner, deps) in config
+        .targets
+        .iter()
+        .map(|target| (&target.name, &target.deps))
+        .chain(config.dependencies.iter().flatten().map(|dep| (&dep.name, &dep.deps)))
+    {
+        for dep in deps.iter().flatten() {
+            if !target_names.contains(dep.as_str()) && !dep_names.contains(dep.as_str()) {
+                errors.push(format!("'{}' depends on unknown '{}'", owner, dep));
+            }
+        }
+    }
+    for target in &config.targets {
+        for dep in target.deps.iter().flatten() {
+            if let Some(dep_target) = config.targets.iter().find(|t| &t.name == dep)
+                && !dep_target.is_library()
+            {
+                errors.push(format!("target '{}' depends on '{}', which is not a library", target.name, dep));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("{}: invalid configuration:\n  - {}", path, errors.join("\n  - "));
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use crate::config::{BuildConfig, ConfigureFile, OptionValue};
+use anyhow::Result;
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Переменные для шаблонов configure_files: метаданные проекта и опции сборки.
+///
+/// `PROJECT_NAME`, `PROJECT_VERSION`, `PROJECT_VERSION_MAJOR/MINOR/PATCH`, те же значения
+/// с префиксом имени проекта (`DEMO_VERSION_MAJOR`), а также каждая опция под своим именем
+/// и под именем дефайна (`enable_ssl` и `ENABLE_SSL`), и результаты `[[checks]]` под их именами.
+/// Bool-значения подставляются как 1 и 0.
+pub fn variables(config: &BuildConfig, check_values: &BTreeMap<String, OptionValue>) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    let project = &config.project;
+    let mut version_parts = project.version.split('.');
+    let major = version_parts.next().unwrap_or("0").to_string();
+    let minor = version_parts.next().unwrap_or("0").to_string();
+    let patch = version_parts.next().unwrap_or("0").to_string();
+    let prefix = project.name.to_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    for name in ["PROJECT", prefix.as_str()] {
+        variables.insert(format!("{}_VERSION", name), project.version.clone());
+        variables.insert(format!("{}_VERSION_MAJOR", name), major.clone());
+        variables.insert(format!("{}_VERSION_MINOR", name), minor.clone());
+        variables.insert(format!("{}_VERSION_PATCH", name), patch.clone());
+    }
+    variables.insert("PROJECT_NAME".to_string(), project.name.clone());
+
+    for (name, option) in config.options.iter().flatten() {
+        let Some(value) = config.option_values.get(name) else { continue; };
+        let value = template_value(value);
+        variables.insert(option.define.clone().unwrap_or_else(|| name.to_uppercase()), value.clone());
+        variables.insert(name.clone(), value);
+    }
+    for (name, value) in check_values {
+        variables.insert(name.clone(), template_value(value));
+    }
+    variables
+}
+
+fn template_value(value: &OptionValue) -> String {
+    match value {
+        OptionValue::Bool(value) => (*value as u8).to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Генерирует все configure_files. Файл перезаписывается, только если его содержимое
+/// изменилось, чтобы не пересобирать зависящие от него объекты.
+/// С `dry_run` только сообщает, какие файлы были бы перезаписаны.
+pub fn generate_all(config: &BuildConfig, check_values: &BTreeMap<String, OptionValue>, dry_run: bool) -> Result<()> {
+    let variables = variables(config, check_values);
+    for file in config.configure_files.iter().flatten() {
+        let content = render_file(file, &variables)?;
+        if dry_run {
+            if fs::read_to_string(&file.output).ok().as_deref() != Some(content.as_str()) {
+                info!(stage = "configure"; "[dry-run] generate {} from {}", file.output, file.input);
+            }
+        } else if write_if_changed(Path::new(&file.output), &content)? {
+            info!(stage = "configure"; "Generated {} from {}", file.output, file.input);
+        }
+    }
+    Ok(())
+}
+
+pub fn render_file(file: &ConfigureFile, variables: &BTreeMap<String, String>) -> Result<String> {
+    let template = fs::read_to_string(&file.input)
+        .map_err(|e| anyhow::anyhow!("configure file {}: {}", file.input, e))?;
+    render(&template, variables).map_err(|e| anyhow::anyhow!("{}:{}", file.input, e))
+}
+
+/// Подставляет `@VAR@` и обрабатывает строки `#cmakedefine VAR ...` и `#cmakedefine01 VAR`.
+pub fn render(template: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    for (index, line) in template.split_inclusive('\n').enumerate() {
+        let line_number = index + 1;
+        let body = line.trim_start();
+        let indent = &line[..line.len() - body.len()];
+        let newline = if line.ends_with('\n') { "\n" } else { "" };
+        if let Some(rest) = body.strip_prefix("#cmakedefine01") {
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            if name.is_empty() {
+                anyhow::bail!("{}: #cmakedefine01 requires a variable name", line_number);
+            }
+            output.push_str(&format!("{}#define {} {}{}", indent, name, is_true(variables.get(name)) as u8, newline));
+        } else if let Some(rest) = body.strip_prefix("#cmakedefine") {
+            let rest = rest.trim();
+            let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if name.is_empty() {
+                anyhow::bail!("{}: #cmakedefine requires a variable name", line_number);
+            }
+            // Значение подставляется только для определённой переменной, как в CMake
+            if is_true(variables.get(name)) {
+                let value = substitute(value.trim(), variables).map_err(|e| anyhow::anyhow!("{}: {}", line_number, e))?;
+                let separator = if value.is_empty() { "" } else { " " };
+                output.push_str(&format!("{}#define {}{}{}{}", indent, name, separator, value, newline));
+            } else {
+                output.push_str(&format!("{}/* #undef {} */{}", indent, name, newline));
+            }
+        } else {
+            output.push_str(&substitute(line, variables).map_err(|e| anyhow::anyhow!("{}: {}", line_number, e))?);
+        }
+    }
+    Ok(output)
+}
+
+/// Значение считается ложным, если переменной нет, она пустая или равна 0/OFF/FALSE/NO.
+fn is_true(value: Option<&String>) -> bool {
+    value.is_some_and(|value| {
+        !value.is_empty() && !["0", "OFF", "FALSE", "NO", "N"].contains(&value.to_uppercase().as_str())
+    })
+}
+
+fn substitute(line: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('@') {
+        let after = &rest[start + 1..];
+        let name_len = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+        // @VAR@ — только если сразу за именем идёт закрывающая @, иначе это обычный символ
+        if name_len > 0 && after[name_len..].starts_with('@') {
+            let name = &after[..name_len];
+            let value = variables
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("undefined variable @{}@", name))?;
+            result.push_str(&rest[..start]);
+            result.push_str(value);
+            rest = &after[name_len + 1..];
+        } else {
+            result.push_str(&rest[..=start]);
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Записывает файл, только если содержимое отличается. Возвращает true, если файл записан.
+pub fn write_if_changed(path: &Path, content: &str) -> Result<bool> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn substitutes_at_variables() {
+        let variables = variables(&[("PROJECT_VERSION", "1.2.3"), ("NAME", "demo")]);
+        assert_eq!(
+            render("#define V \"@PROJECT_VERSION@\"\n#define N @NAME@_@NAME@\n", &variables).unwrap(),
+            "#define V \"1.2.3\"\n#define N demo_demo\n"
+        );
+    }
+
+    #[test]
+    fn leaves_lone_at_signs_and_dollar_braces() {
+        let variables = variables(&[("NAME", "demo")]);
+        let template = "// mail me@example.com, @ alone, @NAME, ${NAME}\n";
+        assert_eq!(render(template, &variables).unwrap(), template);
+    }
+
+    #[test]
+    fn rejects_undefined_variables_with_line_number() {
+        let error = render("ok\n#define X @MISSING@\n", &BTreeMap::new()).unwrap_err();
+        assert_eq!(error.to_string(), "2: undefined variable @MISSING@");
+    }
+
+    #[test]
+    fn renders_cmakedefine() {
+        let variables = variables(&[("HAVE_A", "1"), ("HAVE_B", "OFF"), ("BACKEND", "openssl")]);
+        let template = "#cmakedefine HAVE_A\n#cmakedefine HAVE_B\n#cmakedefine HAVE_C\n  #cmakedefine BACKEND \"@BACKEND@\"\n";
+        assert_eq!(
+            render(template, &variables).unwrap(),
+            "#define HAVE_A\n/* #undef HAVE_B */\n/* #undef HAVE_C */\n  #define BACKEND \"openssl\"\n"
+        );
+    }
+
+    #[test]
+    fn renders_cmakedefine01() {
+        let variables = variables(&[("ENABLE_SSL", "1"), ("ENABLE_ZLIB", "0")]);
+        let template = "#cmakedefine01 ENABLE_SSL\n#cmakedefine01 ENABLE_ZLIB\n#cmakedefine01 UNKNOWN";
+        assert_eq!(
+            render(template, &variables).unwrap(),
+            "#define ENABLE_SSL 1\n#define ENABLE_ZLIB 0\n#define UNKNOWN 0"
+        );
+    }
+
+    #[test]
+    fn rejects_cmakedefine_without_name() {
+        assert!(render("#cmakedefine\n", &BTreeMap::new()).is_err());
+        assert!(render("#cmakedefine01   \n", &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn undefined_variable_in_false_cmakedefine_is_not_an_error() {
+        assert_eq!(render("#cmakedefine HAVE_X @MISSING@\n", &BTreeMap::new()).unwrap(), "/* #undef HAVE_X */\n");
+    }
+}
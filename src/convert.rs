@@ -0,0 +1,434 @@
+use crate::config::{self, BuildConfig, LOCAL_OVERLAY_FILE_NAME};
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::{Array, DocumentMut, Item, RawString, Table, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// Формат по расширению: `.toml`, `.constructor` и `WORKSPACE.local` — TOML,
+    /// `.yaml` и `.yml` — YAML, `.json` — JSON.
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            Some(Format::Yaml)
+        } else if path.ends_with(".json") {
+            Some(Format::Json)
+        } else if path.ends_with(".toml") || path.ends_with(".constructor") || path.ends_with(LOCAL_OVERLAY_FILE_NAME) {
+            Some(Format::Toml)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    #[arg(long, value_enum)]
+    to: Format,
+
+    /// Куда записать результат; по умолчанию рядом с конфигом с расширением формата, "-" — в stdout
+    #[arg(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct FmtArgs {
+    /// Только проверить форматирование, ничего не записывая (для CI)
+    #[arg(long)]
+    check: bool,
+}
+
+/// Сериализует конфиг. Пустые поля опускаются: `toml::Value` не хранит `None`.
+pub fn serialize<T: Serialize>(value: &T, format: Format) -> Result<String> {
+    let value = toml::Value::try_from(value)?;
+    Ok(match format {
+        Format::Toml => toml::to_string(&value)?,
+        Format::Yaml => serde_yaml::to_string(&value)?,
+        Format::Json => serde_json::to_string_pretty(&value)? + "\n",
+    })
+}
+
+pub fn run_convert(args: &ConvertArgs, config_path: &str, force: bool) -> Result<()> {
+    let source_format = source_format(config_path)?;
+    let original = read(config_path)?;
+    let content = canonical(config_path, &original, source_format, args.to)?;
+
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => Path::new(config_path).with_extension(args.to.extension()).to_string_lossy().to_string(),
+    };
+    if output == "-" {
+        print!("{}", content);
+        return Ok(());
+    }
+    if Format::from_path(&output) != Some(args.to) {
+        anyhow::bail!("{}: the extension does not match the {} format", output, args.to.extension());
+    }
+    if Path::new(&output).exists() && !force {
+        anyhow::bail!("{} already exists, use --force to overwrite it", output);
+    }
+    if args.to != Format::Toml
+        && let Some(line) = comment_line(&original, source_format)
+    {
+        warn!("{}:{}: comments are not carried over to {}", config_path, line, args.to.extension());
+    }
+    fs::write(&output, content)?;
+    info!("Converted {} to {}", config_path, output);
+    Ok(())
+}
+
+/// Переписывает конфиг в каноническом порядке полей. Комментарии TOML сохраняются,
+/// YAML с комментариями форматируется только с --force.
+pub fn run_fmt(args: &FmtArgs, config_path: &str, force: bool) -> Result<()> {
+    let format = source_format(config_path)?;
+    let original = read(config_path)?;
+    let formatted = canonical(config_path, &original, format, format)?;
+    if formatted == original {
+        if !args.check {
+            info!("{} is already formatted", config_path);
+        }
+        return Ok(());
+    }
+    if format == Format::Yaml
+        && let Some(line) = comment_line(&original, format)
+        && (args.check || !force)
+    {
+        anyhow::bail!("{}:{}: fmt cannot keep YAML comments; remove them or pass --force to drop them", config_path, line);
+    }
+    if args.check {
+        let line = original
+            .lines()
+            .zip(formatted.lines())
+            .position(|(original, formatted)| original != formatted)
+            .unwrap_or_else(|| original.lines().count().min(formatted.lines().count()))
+            + 1;
+        anyhow::bail!("{}:{}: config is not formatted, run `constructor fmt`", config_path, line);
+    }
+    fs::write(config_path, formatted)?;
+    info!("Formatted {}", config_path);
+    Ok(())
+}
+
+fn source_format(config_path: &str) -> Result<Format> {
+    Format::from_path(config_path).ok_or_else(|| anyhow::anyhow!("{}: unsupported config file format", config_path))
+}
+
+fn read(config_path: &str) -> Result<String> {
+    fs::read_to_string(config_path).map_err(|e| anyhow::anyhow!("{}: {}", config_path, e))
+}
+
+/// Конфиг в формате `to` с полями в порядке объявления `BuildConfig`. Результат разбирается
+/// обратно и сравнивается с исходным, так что ни одно поле не теряется.
+fn canonical(config_path: &str, original: &str, from: Format, to: Format) -> Result<String> {
+    let config: BuildConfig = config::parse_str(config_path, from, original)?;
+    let mut content = serialize(&config, to)?;
+    if from == Format::Toml && to == Format::Toml {
+        content = transfer_comments(original, &content)?;
+    }
+    let reparsed: BuildConfig = config::parse_str(config_path, to, &content)?;
+    if toml::Value::try_from(&config)? != toml::Value::try_from(&reparsed)? {
+        anyhow::bail!("{}: converting to {} changes the config, nothing was written", config_path, to.extension());
+    }
+    Ok(content)
+}
+
+/// Номер первой строки с комментарием. Для YAML это эвристика: `#` вне кавычек в начале
+/// строки или после пробела.
+fn comment_line(content: &str, format: Format) -> Option<usize> {
+    if format == Format::Json {
+        return None;
+    }
+    content.lines().position(|line| {
+        let mut quote = None;
+        let mut previous = ' ';
+        for c in line.chars() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                (None, '#') if previous.is_whitespace() => return true,
+                _ => {},
+            }
+            previous = c;
+        }
+        false
+    }).map(|index| index + 1)
+}
+
+/// Комментарии TOML-файла по логическому пути (`targets[0].name`): так они переносятся
+/// в каноническую раскладку, даже если ключи переставлены или таблица стала заголовком.
+#[derive(Default)]
+struct Comments {
+    /// Строки комментариев перед ключом или заголовком таблицы
+    leading: HashMap<String, String>,
+    /// Комментарий в конце строки
+    trailing: HashMap<String, String>,
+    /// Многострочные массивы с комментариями внутри переносятся как есть
+    values: HashMap<String, Value>,
+}
+
+fn transfer_comments(original: &str, canonical: &str) -> Result<String> {
+    let original: DocumentMut = original.parse()?;
+    let mut document: DocumentMut = canonical.parse()?;
+    let mut comments = Comments::default();
+    comments.collect_table(original.as_table(), "");
+    comments.apply_table(document.as_table_mut(), "");
+    let trailing = comment_lines(Some(original.trailing()));
+    if !trailing.is_empty() {
+        document.set_trailing(format!("\n{}", trailing));
+    }
+    Ok(document.to_string())
+}
+
+impl Comments {
+    fn collect_table(&mut self, table: &Table, path: &str) {
+        self.add(path, table.decor().prefix(), table.decor().suffix());
+        for (key, item) in table.iter() {
+            let child = child_path(path, key);
+            if let Some(key) = table.key(key) {
+                self.add(&child, key.leaf_decor().prefix(), None);
+            }
+            self.collect_item(item, &child);
+        }
+    }
+
+    fn collect_item(&mut self, item: &Item, path: &str) {
+        match item {
+            Item::Table(table) => self.collect_table(table, path),
+            Item::ArrayOfTables(tables) => {
+                for (index, table) in tables.iter().enumerate() {
+                    self.collect_table(table, &format!("{}[{}]", path, index));
+                }
+            },
+            Item::Value(value) => {
+                self.add(path, None, value.decor().suffix());
+                if let Value::Array(array) = value
+                    && has_comments(array)
+                {
+                    self.values.insert(path.to_string(), value.clone());
+                }
+            },
+            Item::None => {},
+        }
+    }
+
+    fn add(&mut self, path: &str, leading: Option<&RawString>, trailing: Option<&RawString>) {
+        let leading = comment_lines(leading);
+        if !leading.is_empty() {
+            self.leading.insert(path.to_string(), leading);
+        }
+        let trailing = comment_lines(trailing);
+        if !trailing.is_empty() {
+            self.trailing.insert(path.to_string(), trailing.trim_end().to_string());
+        }
+    }
+
+    fn apply_table(&self, table: &mut Table, path: &str) {
+        if let Some(leading) = self.leading.get(path) {
+            let prefix = table.decor().prefix().and_then(RawString::as_str).unwrap_or_default().to_string();
+            table.decor_mut().set_prefix(format!("{}{}", prefix, leading));
+        }
+        if let Some(trailing) = self.trailing.get(path) {
+            table.decor_mut().set_suffix(format!(" {}", trailing));
+        }
+        let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+        for key in keys {
+            let child = child_path(path, &key);
+            let is_value = table.get(&key).is_some_and(Item::is_value);
+            if is_value
+                && let Some(leading) = self.leading.get(&child)
+                && let Some(mut key) = table.key_mut(&key)
+            {
+                key.leaf_decor_mut().set_prefix(leading.clone());
+            }
+            if let Some(item) = table.get_mut(&key) {
+                self.apply_item(item, &child);
+            }
+        }
+    }
+
+    fn apply_item(&self, item: &mut Item, path: &str) {
+        match item {
+            Item::Table(table) => self.apply_table(table, path),
+            Item::ArrayOfTables(tables) => {
+                for (index, table) in tables.iter_mut().enumerate() {
+                    self.apply_table(table, &format!("{}[{}]", path, index));
+                }
+            },
+            Item::Value(value) => {
+                if let Some(original) = self.values.get(path) {
+                    *value = original.clone();
+                    value.decor_mut().set_prefix(" ");
+                }
+                match self.trailing.get(path) {
+                    Some(trailing) => value.decor_mut().set_suffix(format!(" {}", trailing)),
+                    None => value.decor_mut().set_suffix(""),
+                }
+            },
+            Item::None => {},
+        }
+    }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+/// Только строки-комментарии из пробелов и комментариев вокруг ключа или таблицы.
+fn comment_lines(raw: Option<&RawString>) -> String {
+    raw.and_then(RawString::as_str)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn has_comments(array: &Array) -> bool {
+    let decor_has_comment = |value: &Value| {
+        [value.decor().prefix(), value.decor().suffix()]
+            .into_iter()
+            .any(|raw| !comment_lines(raw).is_empty())
+    };
+    !comment_lines(Some(array.trailing())).is_empty()
+        || array.iter().any(|value| {
+            decor_has_comment(value) || matches!(value, Value::Array(array) if has_comments(array))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKSPACE: &str = r#"# Демо-проект
+[project]
+name = "demo" # имя
+version = "1.0.0"
+language = "C"
+
+[vars]
+out = "build/${profile}"
+
+[options]
+enable_ssl = { type = "bool", default = false, description = "TLS support" }
+
+# Зависимости
+[[dependencies]]
+name = "zlib"
+source = "system"
+libs = ["z"]
+
+[[targets]]
+# Основной бинарник
+name = "app"
+out_dir = "${out}"
+kind = "executable"
+sources = [
+    "src/main.c", # точка входа
+    "src/util.c",
+]
+defines = ["APP=1"]
+os_target = "linux"
+compiler = "gcc"
+deps = ["zlib"]
+[targets.when."options.enable_ssl"]
+link_libraries = ["ssl"]
+"#;
+
+    fn temp_file(name: &str, content: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("constructor-convert-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name).to_string_lossy().to_string();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn value(path: &str, format: Format, content: &str) -> toml::Value {
+        let config: BuildConfig = config::parse_str(path, format, content).unwrap();
+        toml::Value::try_from(&config).unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_all_formats() {
+        let yaml = canonical("WORKSPACE.toml", WORKSPACE, Format::Toml, Format::Yaml).unwrap();
+        let json = canonical("WORKSPACE.yaml", &yaml, Format::Yaml, Format::Json).unwrap();
+        let toml = canonical("WORKSPACE.json", &json, Format::Json, Format::Toml).unwrap();
+        let original = value("WORKSPACE.toml", Format::Toml, WORKSPACE);
+        assert_eq!(value("WORKSPACE.yaml", Format::Yaml, &yaml), original);
+        assert_eq!(value("WORKSPACE.json", Format::Json, &json), original);
+        assert_eq!(value("WORKSPACE.toml", Format::Toml, &toml), original);
+        // Без комментариев TOML после круга совпадает с каноническим
+        assert_eq!(toml, serialize(&toml::from_str::<BuildConfig>(WORKSPACE).unwrap(), Format::Toml).unwrap());
+    }
+
+    #[test]
+    fn keeps_toml_comments() {
+        let formatted = canonical("WORKSPACE.toml", WORKSPACE, Format::Toml, Format::Toml).unwrap();
+        for comment in ["# Демо-проект", "# имя", "# Зависимости", "# Основной бинарник", "# точка входа"] {
+            assert!(formatted.contains(comment), "{:?} is lost:\n{}", comment, formatted);
+        }
+        assert!(formatted.contains("name = \"demo\" # имя\n"));
+        assert_eq!(value("WORKSPACE.toml", Format::Toml, &formatted), value("WORKSPACE.toml", Format::Toml, WORKSPACE));
+        // Отформатированный конфиг уже канонический
+        assert_eq!(canonical("WORKSPACE.toml", &formatted, Format::Toml, Format::Toml).unwrap(), formatted);
+    }
+
+    #[test]
+    fn fmt_check_reports_first_changed_line() {
+        let path = temp_file("WORKSPACE.toml", WORKSPACE);
+        let error = run_fmt(&FmtArgs { check: true }, &path, false).unwrap_err();
+        assert!(error.to_string().starts_with(&format!("{}:", path)), "{}", error);
+        assert!(error.to_string().ends_with("config is not formatted, run `constructor fmt`"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), WORKSPACE);
+
+        run_fmt(&FmtArgs { check: false }, &path, false).unwrap();
+        assert_ne!(fs::read_to_string(&path).unwrap(), WORKSPACE);
+        run_fmt(&FmtArgs { check: true }, &path, false).unwrap();
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn fmt_refuses_to_drop_yaml_comments() {
+        let yaml = canonical("WORKSPACE.toml", WORKSPACE, Format::Toml, Format::Yaml).unwrap();
+        let path = temp_file("WORKSPACE.yaml", &format!("# комментарий\n{}\n", yaml.replace("project:", "project:  ")));
+        let error = run_fmt(&FmtArgs { check: false }, &path, false).unwrap_err();
+        assert!(error.to_string().contains(":1: fmt cannot keep YAML comments"), "{}", error);
+        run_fmt(&FmtArgs { check: false }, &path, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), yaml);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finds_comment_lines() {
+        assert_eq!(comment_line("a: 1\nb: \"x # y\"\n# c\n", Format::Yaml), Some(3));
+        assert_eq!(comment_line("a: 'x#y'\n", Format::Yaml), None);
+        assert_eq!(comment_line("{\"a\": \"# b\"}", Format::Json), None);
+    }
+
+    #[test]
+    fn detects_format_by_extension() {
+        assert_eq!(Format::from_path("WORKSPACE.constructor"), Some(Format::Toml));
+        assert_eq!(Format::from_path("WORKSPACE.local"), Some(Format::Toml));
+        assert_eq!(Format::from_path("WORKSPACE.yml"), Some(Format::Yaml));
+        assert_eq!(Format::from_path("WORKSPACE.json"), Some(Format::Json));
+        assert_eq!(Format::from_path("WORKSPACE"), None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::config::{BuildSystem, Dependency, DependencyBuild};
+use crate::logging::{self, Prefix};
+use anyhow::Result;
+use log::{debug, info};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Файл в директории конфигурации, который появляется после успешной сборки зависимости.
+const BUILT_MARKER: &str = ".constructor-built";
+
+/// Где собирается зависимость для конкретной конфигурации.
+pub struct BuildLayout
+{
+    /// deps/.build/<name>/<hash конфигурации>
+    pub cache_dir: PathBuf,
+    /// Директория для out-of-source сборки (cmake, autotools)
+    pub build_dir: PathBuf,
+    /// Корень результата: outputs и includes зависимости указываются относительно него.
+    /// Для cmake и autotools это префикс установки, для make и constructor — исходники.
+    pub result_dir: PathBuf,
+}
+
+impl BuildLayout {
+    pub fn new(dep: &Dependency, build: &DependencyBuild, source_dir: &Path, env: &[(String, String)], fingerprint: u64) -> Self {
+        let mut hasher = DefaultHasher::new();
+        build.system.hash(&mut hasher);
+        build.options.hash(&mut hasher);
+        fingerprint.hash(&mut hasher);
+        // Смена компилятора через окружение (своё или `env` проекта) тоже даёт новую конфигурацию
+        env.hash(&mut hasher);
+        for var in ["CC", "CXX", "CFLAGS", "CXXFLAGS", "LDFLAGS"] {
+            std::env::var(var).ok().hash(&mut hasher);
+        }
+        let cache_dir = Path::new("deps/.build").join(&dep.name).join(format!("{:016x}", hasher.finish()));
+        let build_dir = cache_dir.join("build");
+        let result_dir = match build.system {
+            BuildSystem::Cmake | BuildSystem::Autotools => cache_dir.join("install"),
+            BuildSystem::Make | BuildSystem::Constructor => source_dir.to_path_buf(),
+        };
+        Self { cache_dir, build_dir, result_dir }
+    }
+
+    /// Сборка уже выполнялась для этой конфигурации и все ожидаемые файлы на месте.
+    pub fn is_built(&self, build: &DependencyBuild) -> bool {
+        self.cache_dir.join(BUILT_MARKER).exists()
+            && build.outputs.iter().flatten().all(|output| self.result_dir.join(output).exists())
+    }
+
+    pub fn mark_built(&self) -> Result<()> {
+        fs::write(self.cache_dir.join(BUILT_MARKER), "")?;
+        Ok(())
+    }
+
+    pub fn check_outputs(&self, dep: &Dependency, build: &DependencyBuild) -> Result<()> {
+        for output in build.outputs.iter().flatten() {
+            if !self.result_dir.join(output).exists() {
+                anyhow::bail!(
+                    "Dependency '{}' was built but did not produce expected output {}",
+                    dep.name,
+                    self.result_dir.join(output).display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Собирает зависимость с помощью cmake, make или autotools; шаги получают `env` проекта.
+/// Зависимости с системой constructor собирает сам Builder.
+/// С `dry_run` шаги сборки только печатаются.
+pub fn run(
+    dep: &Dependency,
+    build: &DependencyBuild,
+    source_dir: &Path,
+    layout: &BuildLayout,
+    env: &[(String, String)],
+    dry_run: bool,
+) -> Result<()> {
+    let options = build.options.clone().unwrap_or_default();
+    let jobs = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).to_string();
+    if !dry_run {
+        fs::create_dir_all(&layout.build_dir)?;
+    }
+    // cmake и configure требуют абсолютный префикс установки
+    let install_dir = std::path::absolute(&layout.result_dir)?;
+    let source_dir = std::path::absolute(source_dir)?;
+
+    match build.system {
+        BuildSystem::Cmake => {
+            run_step(dep, Command::new("cmake")
+                .arg("-S").arg(&source_dir)
+                .arg("-B").arg(&layout.build_dir)
+                .arg("-DCMAKE_BUILD_TYPE=Release")
+                .arg(format!("-DCMAKE_INSTALL_PREFIX={}", install_dir.display()))
+                .args(&options), env, dry_run)?;
+            run_step(dep, Command::new("cmake")
+                .arg("--build").arg(&layout.build_dir)
+                .arg("--parallel").arg(&jobs), env, dry_run)?;
+            run_step(dep, Command::new("cmake")
+                .arg("--install").arg(&layout.build_dir), env, dry_run)?;
+        },
+        BuildSystem::Autotools => {
+            run_step(dep, Command::new(source_dir.join("configure"))
+                .current_dir(&layout.build_dir)
+                .arg(format!("--prefix={}", install_dir.display()))
+                .args(&options), env, dry_run)?;
+            run_step(dep, Command::new("make")
+                .current_dir(&layout.build_dir)
+                .arg(format!("-j{}", jobs)), env, dry_run)?;
+            run_step(dep, Command::new("make")
+                .current_dir(&layout.build_dir)
+                .arg("install"), env, dry_run)?;
+        },
+        BuildSystem::Make => {
+            run_step(dep, Command::new("make")
+                .current_dir(&source_dir)
+                .arg(format!("-j{}", jobs))
+                .args(&options), env, dry_run)?;
+        },
+        BuildSystem::Constructor => anyhow::bail!("Dependency '{}' is built by constructor itself", dep.name),
+    }
+    Ok(())
+}
+
+fn run_step(dep: &Dependency, command: &mut Command, env: &[(String, String)], dry_run: bool) -> Result<()> {
+    command.envs(env.iter().cloned());
+    if dry_run {
+        info!(target = dep.name.as_str(); "[dry-run] {:?}", command);
+        return Ok(());
+    }
+    debug!(target = dep.name.as_str(); "Running {:?}", command);
+    let status = logging::run_command(command, Prefix::Target(&dep.name))?;
+    if !status.success() {
+        anyhow::bail!("Failed to build dependency: {}", dep.name);
+    }
+    Ok(())
+}
@@ -0,0 +1,419 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{Level, info, log};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// `text` — диагностики выводятся по таргетам; `sarif` — вдобавок записываются в SARIF-файл.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticsFormat {
+    Text,
+    Sarif,
+}
+
+/// Куда по умолчанию пишется SARIF с `--diagnostics-format sarif`.
+pub const DEFAULT_SARIF_FILE: &str = "constructor.sarif";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    /// Уровень результата в SARIF.
+    fn sarif_level(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// Одна диагностика GCC или Clang: `file:line:column: severity: message [-Woption]`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// Флаг, включивший предупреждение, например `-Wunused-variable`
+    pub option: Option<String>,
+    /// Следующие за диагностикой строки: фрагмент кода, `^`, подсказки
+    pub context: Vec<String>,
+}
+
+impl Diagnostic {
+    fn location(&self) -> Option<String> {
+        let file = self.file.as_ref()?;
+        Some(match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", file, line, column),
+            (Some(line), None) => format!("{}:{}", file, line),
+            _ => file.clone(),
+        })
+    }
+}
+
+/// Разбирает stderr компилятора. Строки, не похожие на диагностику, становятся контекстом
+/// предыдущей; строки до первой диагностики (`In file included from ...`) возвращаются отдельно.
+pub fn parse(stderr: &str) -> (Vec<Diagnostic>, Vec<String>) {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    let mut other = vec![];
+    for line in stderr.lines() {
+        if let Some(diagnostic) = parse_line(line) {
+            diagnostics.push(diagnostic);
+        } else if let Some(last) = diagnostics.last_mut() {
+            last.context.push(line.to_string());
+        } else if !line.trim().is_empty() {
+            other.push(line.to_string());
+        }
+    }
+    (diagnostics, other)
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    const SEVERITIES: [(&str, Severity); 4] = [
+        (": fatal error: ", Severity::Error),
+        (": error: ", Severity::Error),
+        (": warning: ", Severity::Warning),
+        (": note: ", Severity::Note),
+    ];
+    let (index, marker, severity) = SEVERITIES
+        .iter()
+        .filter_map(|(marker, severity)| line.find(marker).map(|index| (index, *marker, *severity)))
+        .min_by_key(|(index, _, _)| *index)?;
+    let location = &line[..index];
+    let mut message = line[index + marker.len()..].to_string();
+
+    let (file, line_number, column) = split_location(location);
+    if file.is_none() {
+        message = format!("{}: {}", location, message);
+    }
+
+    // GCC: [-Wunused-variable], Clang: [-Werror,-Wunused-variable] — флагом считается последний
+    let mut option = None;
+    if message.ends_with(']')
+        && let Some(start) = message.rfind(" [-")
+    {
+        let flags = &message[start + 2..message.len() - 1];
+        option = flags.rsplit(',').next().map(str::to_string);
+        message.truncate(start);
+    }
+    Some(Diagnostic { file, line: line_number, column, severity, message, option, context: vec![] })
+}
+
+/// `file:line:column` или `file:line`; без номера строки это не файл, а программа
+/// (`cc1plus`, `collect2`, `/usr/bin/ld`), и позиции нет. Двоеточие после буквы диска
+/// (`C:\src\main.c:12`) разделителем не считается.
+fn split_location(location: &str) -> (Option<String>, Option<u32>, Option<u32>) {
+    let (drive, rest) = location.split_at(if has_drive_prefix(location) { 2 } else { 0 });
+    let Some((file, last)) = rest.rsplit_once(':') else {
+        return (None, None, None);
+    };
+    let Ok(last) = last.parse() else {
+        return (None, None, None);
+    };
+    if file.is_empty() {
+        return (None, None, None);
+    }
+    if let Some((name, line)) = file.rsplit_once(':')
+        && let Ok(line) = line.parse()
+        && !name.is_empty()
+    {
+        return (Some(format!("{}{}", drive, name)), Some(line), Some(last));
+    }
+    (Some(format!("{}{}", drive, file)), Some(last), None)
+}
+
+/// `C:\...` или `C:/...` — путь Windows с буквой диска.
+fn has_drive_prefix(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && matches!(bytes[2], b'\\' | b'/')
+}
+
+/// База для относительных путей в SARIF: корень WORKSPACE, из которого запускается компилятор.
+const SRCROOT: &str = "%SRCROOT%";
+
+/// `artifactLocation` для пути из вывода компилятора: абсолютный путь становится `file://` URI,
+/// относительный — URI-ссылкой от `%SRCROOT%`.
+fn artifact_location(path: &str) -> serde_json::Value {
+    match file_uri(path) {
+        Some(uri) => json!({"uri": uri}),
+        None => json!({"uri": encode_uri_path(path), "uriBaseId": SRCROOT}),
+    }
+}
+
+/// `file://` URI абсолютного пути (Unix или Windows с буквой диска); для относительного — None.
+fn file_uri(path: &str) -> Option<String> {
+    if path.starts_with('/') {
+        Some(format!("file://{}", encode_uri_path(path)))
+    } else if has_drive_prefix(path) {
+        Some(format!("file:///{}{}", &path[..2], encode_uri_path(&path[2..].replace('\\', "/"))))
+    } else {
+        None
+    }
+}
+
+/// Кодирует путь для URI: всё, кроме незарезервированных символов и `/`, записывается как `%XX`.
+fn encode_uri_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Диагностики одного таргета со всех его команд сборки.
+#[derive(Default)]
+struct TargetDiagnostics {
+    /// Компилятор таргета, в SARIF это инструмент
+    tool: String,
+    diagnostics: Vec<Diagnostic>,
+    other: Vec<String>,
+}
+
+/// Собирает диагностики параллельно собираемых таргетов.
+#[derive(Default)]
+pub struct Diagnostics {
+    targets: Mutex<BTreeMap<String, TargetDiagnostics>>,
+}
+
+impl Diagnostics {
+    /// Разбирает stderr команды сборки таргета.
+    pub fn add(&self, target: &str, tool: &str, stderr: &[u8]) {
+        let stderr = String::from_utf8_lossy(stderr);
+        if stderr.trim().is_empty() {
+            return;
+        }
+        let (diagnostics, other) = parse(&stderr);
+        let mut targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = targets.entry(target.to_string()).or_default();
+        // Инструмент в SARIF — компилятор таргета, а не ar
+        if entry.tool.is_empty() || tool != "ar" {
+            entry.tool = tool.to_string();
+        }
+        entry.diagnostics.extend(diagnostics);
+        entry.other.extend(other);
+    }
+
+    /// Выводит диагностики таргета одним блоком: ошибки и предупреждения на своих уровнях
+    /// лога, с файлом, строкой, столбцом и флагом в отдельных полях. Пока блок выводится,
+    /// мьютекс занят, поэтому блоки разных таргетов не перемешиваются.
+    pub fn print(&self, target: &str) {
+        let targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = targets.get(target) else {
+            return;
+        };
+        for line in &entry.other {
+            info!(target = target; "{}", line);
+        }
+        for diagnostic in &entry.diagnostics {
+            let mut text = diagnostic.location().map(|location| format!("{}: ", location)).unwrap_or_default();
+            if diagnostic.severity == Severity::Note {
+                text.push_str("note: ");
+            }
+            text.push_str(&diagnostic.message);
+            if let Some(option) = &diagnostic.option {
+                text.push_str(&format!(" [{}]", option));
+            }
+            for line in &diagnostic.context {
+                text.push('\n');
+                text.push_str(line);
+            }
+            let level = match diagnostic.severity {
+                Severity::Error => Level::Error,
+                Severity::Warning => Level::Warn,
+                Severity::Note => Level::Info,
+            };
+            let severity = diagnostic.severity.sarif_level();
+            let option = diagnostic.option.as_deref().unwrap_or_default();
+            // Позиция в полях события есть, только если компилятор её указал
+            match &diagnostic.file {
+                Some(file) => log!(
+                    level,
+                    target = target,
+                    severity = severity,
+                    file = file.as_str(),
+                    line = diagnostic.line.unwrap_or_default(),
+                    column = diagnostic.column.unwrap_or_default(),
+                    option = option;
+                    "{}", text
+                ),
+                None => log!(level, target = target, severity = severity, option = option; "{}", text),
+            }
+        }
+        let count = |severity| entry.diagnostics.iter().filter(|diagnostic| diagnostic.severity == severity).count();
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+        if errors + warnings > 0 {
+            info!(target = target; "{} error(s), {} warning(s)", errors, warnings);
+        }
+    }
+
+    /// Записывает диагностики в SARIF 2.1.0: по одному run на таргет, инструмент — его компилятор.
+    /// `path` = "-" — в stdout.
+    pub fn write_sarif(&self, path: &str) -> Result<()> {
+        let targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let root = std::env::current_dir()?;
+        let srcroot = file_uri(&root.to_string_lossy())
+            .map(|uri| format!("{}/", uri.trim_end_matches('/')))
+            .ok_or_else(|| anyhow::anyhow!("Cannot make a URI for {}", root.display()))?;
+        let runs: Vec<serde_json::Value> = targets
+            .iter()
+            .map(|(target, entry)| {
+                let results: Vec<serde_json::Value> = entry
+                    .diagnostics
+                    .iter()
+                    .map(|diagnostic| {
+                        let mut result = json!({
+                            "ruleId": diagnostic.option.as_deref().unwrap_or(diagnostic.severity.sarif_level()),
+                            "level": diagnostic.severity.sarif_level(),
+                            "message": {"text": diagnostic.message},
+                        });
+                        if let Some(file) = &diagnostic.file {
+                            let mut region = serde_json::Map::new();
+                            if let Some(line) = diagnostic.line {
+                                region.insert("startLine".to_string(), json!(line));
+                            }
+                            if let Some(column) = diagnostic.column {
+                                region.insert("startColumn".to_string(), json!(column));
+                            }
+                            let mut location = json!({"artifactLocation": artifact_location(file)});
+                            if !region.is_empty() {
+                                location["region"] = serde_json::Value::Object(region);
+                            }
+                            result["locations"] = json!([{"physicalLocation": location}]);
+                        }
+                        result
+                    })
+                    .collect();
+                // Инструмент называется по имени файла компилятора, а не по полному пути
+                let tool = Path::new(&entry.tool).file_name().map_or(entry.tool.clone(), |name| name.to_string_lossy().to_string());
+                json!({
+                    "tool": {"driver": {"name": tool}},
+                    "automationDetails": {"id": format!("{}/", target)},
+                    "originalUriBaseIds": {SRCROOT: {"uri": srcroot}},
+                    "results": results,
+                })
+            })
+            .collect();
+        let sarif = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": runs,
+        });
+        let content = serde_json::to_string_pretty(&sarif)? + "\n";
+        if path == "-" {
+            print!("{}", content);
+            return Ok(());
+        }
+        fs::write(path, content).map_err(|e| anyhow::anyhow!("Failed to write diagnostics {}: {}", path, e))?;
+        info!("Wrote {} diagnostic(s) to {}", targets.values().map(|entry| entry.diagnostics.len()).sum::<usize>(), path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gcc_warning() {
+        let diagnostic = parse_line("src/main.c:12:5: warning: unused variable 'x' [-Wunused-variable]").unwrap();
+        assert_eq!(diagnostic.file.as_deref(), Some("src/main.c"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(12), Some(5)));
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(diagnostic.message, "unused variable 'x'");
+        assert_eq!(diagnostic.option.as_deref(), Some("-Wunused-variable"));
+    }
+
+    #[test]
+    fn takes_last_flag_of_clang_option() {
+        let diagnostic = parse_line("a.c:3:9: error: unused variable 'y' [-Werror,-Wunused-variable]").unwrap();
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.message, "unused variable 'y'");
+        assert_eq!(diagnostic.option.as_deref(), Some("-Wunused-variable"));
+    }
+
+    #[test]
+    fn parses_fatal_error_and_note() {
+        let fatal = parse_line("a.c:1:10: fatal error: foo.h: No such file or directory").unwrap();
+        assert_eq!(fatal.severity, Severity::Error);
+        assert_eq!(fatal.message, "foo.h: No such file or directory");
+        assert_eq!(fatal.option, None);
+        let note = parse_line("a.c:7: note: declared here").unwrap();
+        assert_eq!(note.severity, Severity::Note);
+        assert_eq!((note.line, note.column), (Some(7), None));
+    }
+
+    #[test]
+    fn keeps_program_name_of_linker_lines() {
+        let collect2 = parse_line("collect2: error: ld returned 1 exit status").unwrap();
+        assert_eq!(collect2.file, None);
+        assert_eq!((collect2.line, collect2.column), (None, None));
+        assert_eq!(collect2.message, "collect2: ld returned 1 exit status");
+
+        let lld = parse_line("ld.lld: error: undefined symbol: cos").unwrap();
+        assert_eq!(lld.file, None);
+        assert_eq!(lld.message, "ld.lld: undefined symbol: cos");
+
+        let ld = parse_line("/usr/bin/ld: warning: libm.so.6, needed by libfoo.so, not found").unwrap();
+        assert_eq!(ld.file, None);
+        assert_eq!(ld.severity, Severity::Warning);
+        assert_eq!(ld.message, "/usr/bin/ld: libm.so.6, needed by libfoo.so, not found");
+    }
+
+    #[test]
+    fn parses_windows_drive_paths() {
+        let diagnostic = parse_line("C:\\src\\main.c:12:5: warning: unused variable 'x'").unwrap();
+        assert_eq!(diagnostic.file.as_deref(), Some("C:\\src\\main.c"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(12), Some(5)));
+
+        let diagnostic = parse_line("C:\\src\\main.c:12: error: expected ';'").unwrap();
+        assert_eq!(diagnostic.file.as_deref(), Some("C:\\src\\main.c"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(12), None));
+    }
+
+    #[test]
+    fn splits_locations() {
+        assert_eq!(split_location("a.c:1:2"), (Some("a.c".to_string()), Some(1), Some(2)));
+        assert_eq!(split_location("a.c:1"), (Some("a.c".to_string()), Some(1), None));
+        assert_eq!(split_location("D:/x/a.c:4"), (Some("D:/x/a.c".to_string()), Some(4), None));
+        assert_eq!(split_location("cc1plus"), (None, None, None));
+        assert_eq!(split_location("a.c:x"), (None, None, None));
+        assert_eq!(split_location(":3"), (None, None, None));
+        assert_eq!(split_location("C:\\a.c"), (None, None, None));
+    }
+
+    #[test]
+    fn groups_context_lines() {
+        let stderr = "In file included from a.c:1:\n\
+                      b.h:2:3: warning: unused variable 'z' [-Wunused-variable]\n    \
+                      2 |   int z;\n      \
+                      |       ^\n\
+                      a.c:5:1: error: expected ';'\n";
+        let (diagnostics, other) = parse(stderr);
+        assert_eq!(other, vec!["In file included from a.c:1:".to_string()]);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file.as_deref(), Some("b.h"));
+        assert_eq!(diagnostics[0].context, vec!["    2 |   int z;".to_string(), "      |       ^".to_string()]);
+        assert!(diagnostics[1].context.is_empty());
+    }
+
+    #[test]
+    fn builds_artifact_locations() {
+        assert_eq!(artifact_location("/home/me/a b.c"), json!({"uri": "file:///home/me/a%20b.c"}));
+        assert_eq!(artifact_location("C:\\src\\main.c"), json!({"uri": "file:///C:/src/main.c"}));
+        assert_eq!(artifact_location("src/main.c"), json!({"uri": "src/main.c", "uriBaseId": SRCROOT}));
+        assert_eq!(artifact_location("../lib/x#1.c"), json!({"uri": "../lib/x%231.c", "uriBaseId": SRCROOT}));
+    }
+}
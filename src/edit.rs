@@ -0,0 +1,460 @@
+use crate::config;
+use anyhow::Result;
+use log::info;
+use clap::{ArgGroup, Args, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, value};
+
+const KINDS: [&str; 3] = ["executable", "staticlib", "dynamiclib"];
+const OS_TARGETS: [&str; 3] = ["linux", "macos", "windows"];
+
+#[derive(Subcommand, Debug)]
+pub enum TargetCommand {
+    /// Добавляет таргет в конец WORKSPACE
+    Add(TargetAddArgs),
+    /// Меняет поля существующего таргета
+    Set(TargetSetArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DepCommand {
+    /// Добавляет зависимость
+    Add(DepAddArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TargetAddArgs {
+    name: String,
+
+    #[arg(long, default_value = "executable", value_parser = KINDS)]
+    kind: String,
+
+    /// Исходный файл, можно указать несколько раз
+    #[arg(long = "source", value_name = "FILE", required = true)]
+    sources: Vec<String>,
+
+    /// По умолчанию как у первого таргета в файле, иначе gcc
+    #[arg(long)]
+    compiler: Option<String>,
+
+    /// По умолчанию как у первого таргета в файле, иначе build
+    #[arg(long)]
+    out_dir: Option<String>,
+
+    #[arg(long, value_parser = OS_TARGETS)]
+    os_target: Option<String>,
+
+    /// Зависимость или библиотечный таргет, можно указать несколько раз
+    #[arg(long = "dep", value_name = "NAME")]
+    deps: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TargetSetArgs {
+    name: String,
+
+    #[arg(long)]
+    enabled: Option<bool>,
+
+    #[arg(long, value_parser = KINDS)]
+    kind: Option<String>,
+
+    #[arg(long)]
+    compiler: Option<String>,
+
+    #[arg(long)]
+    out_dir: Option<String>,
+
+    #[arg(long, value_parser = OS_TARGETS)]
+    os_target: Option<String>,
+
+    #[arg(long)]
+    description: Option<String>,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("source").required(true).args(["git", "path", "archive", "system"])))]
+#[command(group(ArgGroup::new("revision").args(["tag", "rev", "branch"])))]
+pub struct DepAddArgs {
+    name: String,
+
+    /// URL git-репозитория
+    #[arg(long)]
+    git: Option<String>,
+
+    /// Путь к локальной директории
+    #[arg(long)]
+    path: Option<String>,
+
+    /// URL архива (tar.gz, tar.xz, zip), требует --sha256
+    #[arg(long, requires = "sha256")]
+    archive: Option<String>,
+
+    /// Системная библиотека, подключается через --link
+    #[arg(long)]
+    system: bool,
+
+    #[arg(long, requires = "git")]
+    tag: Option<String>,
+
+    #[arg(long, requires = "git")]
+    rev: Option<String>,
+
+    #[arg(long, requires = "git")]
+    branch: Option<String>,
+
+    #[arg(long, requires = "archive")]
+    sha256: Option<String>,
+
+    /// Библиотека для линковки ("z" превращается в -lz), можно указать несколько раз
+    #[arg(long = "link", value_name = "LIB")]
+    link_libraries: Vec<String>,
+}
+
+pub fn run_target(command: &TargetCommand, config_path: &str) -> Result<()> {
+    match command {
+        TargetCommand::Add(args) => edit(config_path, Path::new(config_path), |doc| add_target(doc, args)),
+        TargetCommand::Set(args) => {
+            // Таргет может быть определён в файле, подключённом через include
+            let path = find_definition(Path::new(config_path), "targets", &args.name)?
+                .ok_or_else(|| anyhow::anyhow!("{}: target '{}' not found", config_path, args.name))?;
+            edit(config_path, &path, |doc| set_target(doc, args))
+        },
+    }
+}
+
+pub fn run_dep(command: &DepCommand, config_path: &str) -> Result<()> {
+    match command {
+        DepCommand::Add(args) => edit(config_path, Path::new(config_path), |doc| add_dep(doc, args)),
+    }
+}
+
+fn add_target(doc: &mut DocumentMut, args: &TargetAddArgs) -> Result<String> {
+    if find_entry(doc, "targets", &args.name)?.is_some() {
+        anyhow::bail!("target '{}' already exists", args.name);
+    }
+    // Значения по умолчанию берём у первого таргета, чтобы новый был похож на соседей
+    let first = first_entry(doc, "targets");
+    let inherited = |key: &str, fallback: &str| {
+        first
+            .and_then(|table| table.get(key))
+            .and_then(|item| item.as_str())
+            .unwrap_or(fallback)
+            .to_string()
+    };
+    let mut table = Table::new();
+    table["name"] = value(&args.name);
+    table["out_dir"] = value(args.out_dir.clone().unwrap_or_else(|| inherited("out_dir", "build")));
+    table["kind"] = value(&args.kind);
+    table["sources"] = value(string_array(&args.sources));
+    table["os_target"] = value(args.os_target.clone().unwrap_or_else(|| inherited("os_target", std::env::consts::OS)));
+    table["compiler"] = value(args.compiler.clone().unwrap_or_else(|| inherited("compiler", "gcc")));
+    if !args.deps.is_empty() {
+        table["deps"] = value(string_array(&args.deps));
+    }
+    entries(doc, "targets")?.push(table);
+    Ok(format!("Added target '{}'", args.name))
+}
+
+fn set_target(doc: &mut DocumentMut, args: &TargetSetArgs) -> Result<String> {
+    let table = find_entry(doc, "targets", &args.name)?
+        .ok_or_else(|| anyhow::anyhow!("target '{}' not found", args.name))?;
+    let mut changed = vec![];
+    if let Some(enabled) = args.enabled {
+        table["enabled"] = value(enabled);
+        changed.push("enabled");
+    }
+    let strings = [
+        ("kind", &args.kind),
+        ("compiler", &args.compiler),
+        ("out_dir", &args.out_dir),
+        ("os_target", &args.os_target),
+        ("description", &args.description),
+    ];
+    for (key, new_value) in strings {
+        if let Some(new_value) = new_value {
+            table[key] = value(new_value);
+            changed.push(key);
+        }
+    }
+    if changed.is_empty() {
+        anyhow::bail!("nothing to set, pass at least one of --enabled, --kind, --compiler, --out-dir, --os-target, --description");
+    }
+    Ok(format!("Updated target '{}': {}", args.name, changed.join(", ")))
+}
+
+fn add_dep(doc: &mut DocumentMut, args: &DepAddArgs) -> Result<String> {
+    if find_entry(doc, "dependencies", &args.name)?.is_some() {
+        anyhow::bail!("dependency '{}' already exists", args.name);
+    }
+    let mut table = Table::new();
+    table["name"] = value(&args.name);
+    if let Some(url) = &args.git {
+        table["source"] = value("git");
+        table["location"] = value(url);
+        if let Some(rev) = args.tag.as_ref().or(args.rev.as_ref()).or(args.branch.as_ref()) {
+            table["rev"] = value(rev);
+        }
+    } else if let Some(path) = &args.path {
+        table["source"] = value("local");
+        table["location"] = value(path);
+    } else if let Some(url) = &args.archive {
+        table["source"] = value("archive");
+        table["url"] = value(url);
+        table["sha256"] = value(args.sha256.clone().unwrap_or_default());
+    } else {
+        table["source"] = value("system");
+    }
+    if !args.link_libraries.is_empty() {
+        table["link_libraries"] = value(string_array(&args.link_libraries));
+    }
+    entries(doc, "dependencies")?.push(table);
+    Ok(format!("Added dependency '{}'", args.name))
+}
+
+/// Правит TOML-файл на месте, сохраняя комментарии и оформление. Если конфиг до правки
+/// загружался, а после — нет, исходное содержимое файла возвращается и правка отклоняется.
+fn edit(config_path: &str, path: &Path, change: impl FnOnce(&mut DocumentMut) -> Result<String>) -> Result<()> {
+    let display = path.display().to_string();
+    let original = read(path)?;
+    let mut doc: DocumentMut = original.parse().map_err(|e| anyhow::anyhow!("{}: {}", display, e))?;
+    let message = change(&mut doc).map_err(|e| anyhow::anyhow!("{}: {}", display, e))?;
+
+    let load_options = config::LoadOptions::default();
+    let was_valid = config::load_config(config_path, &load_options).is_ok();
+    fs::write(path, doc.to_string())?;
+    if was_valid
+        && let Err(e) = config::load_config(config_path, &load_options)
+    {
+        fs::write(path, original)?;
+        anyhow::bail!("{}\nThe change was not applied.", e);
+    }
+    info!("{} in {}", message, display);
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String> {
+    let display = path.display();
+    if path.extension().is_some_and(|extension| ["yaml", "yml", "json"].iter().any(|other| extension == *other)) {
+        anyhow::bail!(
+            "{}: editing commands support only TOML configs, because YAML and JSON cannot be edited \
+            without losing comments and layout; edit this file by hand",
+            display
+        );
+    }
+    fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", display, e))
+}
+
+/// Файл, в котором определён элемент секции: сам конфиг или один из подключённых через include.
+fn find_definition(path: &Path, section: &str, name: &str) -> Result<Option<PathBuf>> {
+    let content = read(path)?;
+    let mut doc: DocumentMut = content.parse().map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    if find_entry(&mut doc, section, name)?.is_some() {
+        return Ok(Some(path.to_path_buf()));
+    }
+    let base = path.parent().unwrap_or(Path::new(""));
+    let patterns: Vec<String> = doc
+        .get("include")
+        .and_then(|item| item.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|pattern| pattern.as_str().map(str::to_string))
+        .collect();
+    for pattern in patterns {
+        let mut files = glob::glob(&base.join(&pattern).to_string_lossy())?.collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+        for file in files {
+            if let Some(found) = find_definition(&file, section, name)? {
+                return Ok(Some(found));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Элементы `[[section]]`; секция создаётся, если её ещё нет.
+fn entries<'a>(doc: &'a mut DocumentMut, section: &str) -> Result<&'a mut ArrayOfTables> {
+    let item = doc.entry(section).or_insert(Item::ArrayOfTables(ArrayOfTables::new()));
+    item.as_array_of_tables_mut().ok_or_else(|| {
+        anyhow::anyhow!("'{}' is written as an inline array, editing supports only [[{}]] tables", section, section)
+    })
+}
+
+fn find_entry<'a>(doc: &'a mut DocumentMut, section: &str, name: &str) -> Result<Option<&'a mut Table>> {
+    if doc.get(section).is_none() {
+        return Ok(None);
+    }
+    Ok(entries(doc, section)?
+        .iter_mut()
+        .find(|table| table.get("name").and_then(|item| item.as_str()) == Some(name)))
+}
+
+fn first_entry<'a>(doc: &'a DocumentMut, section: &str) -> Option<&'a Table> {
+    doc.get(section)
+        .and_then(|item| item.as_array_of_tables())
+        .and_then(|tables| tables.iter().next())
+}
+
+fn string_array(values: &[String]) -> Array {
+    values.iter().map(String::as_str).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKSPACE: &str = r#"# Демо-проект
+[project]
+name = "demo"
+version = "1.0"
+language = "C"
+
+[[dependencies]]
+name = "zlib"  # системная
+source = "system"
+
+# Основной бинарник
+[[targets]]
+name = "app"
+out_dir = "out"       # выровнено
+kind = "executable"
+sources = ["main.c"]
+os_target = "linux"
+compiler = "clang"
+"#;
+
+    fn doc() -> DocumentMut {
+        WORKSPACE.parse().unwrap()
+    }
+
+    fn add_args(name: &str) -> TargetAddArgs {
+        TargetAddArgs {
+            name: name.to_string(),
+            kind: "staticlib".to_string(),
+            sources: vec!["util.c".to_string(), "extra.c".to_string()],
+            compiler: None,
+            out_dir: None,
+            os_target: None,
+            deps: vec!["zlib".to_string()],
+        }
+    }
+
+    fn set_args(name: &str) -> TargetSetArgs {
+        TargetSetArgs { name: name.to_string(), enabled: None, kind: None, compiler: None, out_dir: None, os_target: None, description: None }
+    }
+
+    fn dep_args(name: &str) -> DepAddArgs {
+        DepAddArgs {
+            name: name.to_string(),
+            git: None,
+            path: None,
+            archive: None,
+            system: false,
+            tag: None,
+            rev: None,
+            branch: None,
+            sha256: None,
+            link_libraries: vec![],
+        }
+    }
+
+    #[test]
+    fn adds_target_after_existing_ones() {
+        let mut doc = doc();
+        assert_eq!(add_target(&mut doc, &add_args("util")).unwrap(), "Added target 'util'");
+        // Исходный текст остаётся как был, новый таргет дописывается в конец с настройками соседа
+        let added = r#"
+[[targets]]
+name = "util"
+out_dir = "out"
+kind = "staticlib"
+sources = ["util.c", "extra.c"]
+os_target = "linux"
+compiler = "clang"
+deps = ["zlib"]
+"#;
+        assert_eq!(doc.to_string(), format!("{}{}", WORKSPACE, added));
+        let error = add_target(&mut doc, &add_args("app")).unwrap_err();
+        assert_eq!(error.to_string(), "target 'app' already exists");
+    }
+
+    #[test]
+    fn sets_target_fields_in_place() {
+        let mut doc = doc();
+        let args = TargetSetArgs { enabled: Some(false), compiler: Some("gcc".to_string()), ..set_args("app") };
+        assert_eq!(set_target(&mut doc, &args).unwrap(), "Updated target 'app': enabled, compiler");
+        let expected = WORKSPACE.replace("compiler = \"clang\"\n", "compiler = \"gcc\"\nenabled = false\n");
+        assert_eq!(doc.to_string(), expected);
+
+        let error = set_target(&mut doc, &set_args("app")).unwrap_err();
+        assert!(error.to_string().starts_with("nothing to set"), "{}", error);
+        let error = set_target(&mut doc, &TargetSetArgs { enabled: Some(true), ..set_args("tool") }).unwrap_err();
+        assert_eq!(error.to_string(), "target 'tool' not found");
+    }
+
+    #[test]
+    fn adds_dependencies_of_every_source() {
+        let mut doc = doc();
+        let git = DepAddArgs { git: Some("https://example.com/fmt.git".to_string()), tag: Some("10.2.1".to_string()), ..dep_args("fmt") };
+        add_dep(&mut doc, &git).unwrap();
+        add_dep(&mut doc, &DepAddArgs { path: Some("../mylib".to_string()), ..dep_args("mylib") }).unwrap();
+        let archive = DepAddArgs {
+            archive: Some("https://example.com/z.tar.gz".to_string()),
+            sha256: Some("abc".to_string()),
+            ..dep_args("z")
+        };
+        add_dep(&mut doc, &archive).unwrap();
+        add_dep(&mut doc, &DepAddArgs { system: true, link_libraries: vec!["m".to_string()], ..dep_args("m") }).unwrap();
+        let content = doc.to_string();
+        assert!(content.starts_with("# Демо-проект\n"));
+        assert!(content.contains("name = \"zlib\"  # системная\n"));
+        assert!(content.contains("out_dir = \"out\"       # выровнено\n"));
+        let config: config::BuildConfig = toml::from_str(&content).unwrap();
+        let deps: Vec<(&str, String, Option<&str>)> = config
+            .dependencies
+            .iter()
+            .flatten()
+            .map(|dep| (dep.name.as_str(), dep.source.to_string(), dep.location.as_deref().or(dep.url.as_deref())))
+            .collect();
+        assert_eq!(
+            deps,
+            [
+                ("zlib", "system".to_string(), None),
+                ("fmt", "git".to_string(), Some("https://example.com/fmt.git")),
+                ("mylib", "local".to_string(), Some("../mylib")),
+                ("z", "archive".to_string(), Some("https://example.com/z.tar.gz")),
+                ("m", "system".to_string(), None),
+            ]
+        );
+        assert_eq!(config.dependencies.as_ref().unwrap()[1].rev.as_deref(), Some("10.2.1"));
+        let error = add_dep(&mut doc, &DepAddArgs { system: true, ..dep_args("zlib") }).unwrap_err();
+        assert_eq!(error.to_string(), "dependency 'zlib' already exists");
+    }
+
+    #[test]
+    fn rejects_inline_arrays() {
+        let mut doc: DocumentMut = "targets = []\n".parse().unwrap();
+        let error = add_target(&mut doc, &add_args("util")).unwrap_err();
+        assert_eq!(error.to_string(), "'targets' is written as an inline array, editing supports only [[targets]] tables");
+    }
+
+    #[test]
+    fn reverts_changes_that_break_the_config() {
+        let dir = std::env::temp_dir().join(format!("constructor-edit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("WORKSPACE.toml");
+        let path_str = path.to_string_lossy().to_string();
+        fs::write(&path, WORKSPACE).unwrap();
+
+        // Таргет с неизвестной зависимостью не проходит проверку конфига
+        let args = TargetAddArgs { deps: vec!["missing".to_string()], ..add_args("util") };
+        let error = edit(&path_str, &path, |doc| add_target(doc, &args)).unwrap_err();
+        assert!(error.to_string().ends_with("The change was not applied."), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), WORKSPACE);
+
+        edit(&path_str, &path, |doc| add_target(doc, &add_args("util"))).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with(WORKSPACE));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use crate::config::{self, BuildConfig, OsTarget, ProjectConfig, TargetConfig, TargetKind};
+use crate::convert::{self, Format};
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Language {
+    C,
+    #[value(name = "cpp", alias = "c++")]
+    Cpp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    Executable,
+    Staticlib,
+    Dynamiclib,
+}
+
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// Имя проекта и основного таргета; по умолчанию имя текущей директории
+    #[arg(long)]
+    name: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = Language::C)]
+    language: Language,
+
+    #[arg(long, value_enum, default_value_t = Kind::Executable)]
+    kind: Kind,
+
+    /// Формат конфига; по умолчанию определяется по расширению --config
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Добавить таргет с тестами из tests/
+    #[arg(long)]
+    with_test: bool,
+}
+
+impl Language {
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Language::C => &["c"],
+            Language::Cpp => &["cpp", "cc", "cxx"],
+        }
+    }
+
+    fn compiler(self) -> &'static str {
+        match self {
+            Language::C => "gcc",
+            Language::Cpp => "g++",
+        }
+    }
+}
+
+impl From<Kind> for TargetKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Executable => TargetKind::Executable,
+            Kind::Staticlib => TargetKind::Staticlib,
+            Kind::Dynamiclib => TargetKind::Dynamiclib,
+        }
+    }
+}
+
+/// Создаёт WORKSPACE в текущей директории. Если в src/ (и tests/) уже есть исходники,
+/// они попадают в sources, иначе создаются файлы-примеры.
+pub fn run(args: &InitArgs, config_path: &str, force: bool) -> Result<()> {
+    let (path, format) = output_path(config_path, args.format)?;
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists, use --force to overwrite it", path.display());
+    }
+
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => std::env::current_dir()?
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "app".to_string()),
+    };
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    let language = args.language;
+    let kind = TargetKind::from(args.kind);
+    let is_library = kind != TargetKind::Executable;
+
+    let mut sources = find_sources("src", language)?;
+    if sources.is_empty() {
+        sources = write_samples(&name, language, is_library)?;
+    }
+    let mut main_target = target(&name, kind, sources, language);
+    if is_library && Path::new("include").is_dir() {
+        main_target.public_includes = Some(vec!["include".to_string()]);
+    }
+    let mut targets = vec![main_target];
+
+    if args.with_test {
+        let mut sources = find_sources("tests", language)?;
+        if sources.is_empty() {
+            sources = vec![write_test_sample(&name, language, is_library)?];
+        }
+        let mut test = target(&format!("{}_test", name), TargetKind::Executable, sources, language);
+        if is_library {
+            test.deps = Some(vec![name.clone()]);
+        }
+        targets.push(test);
+    }
+
+    let config = BuildConfig {
+        schema: None,
+        project: ProjectConfig {
+            name,
+            version: "0.1.0".to_string(),
+            language: match language {
+                Language::C => "C".to_string(),
+                Language::Cpp => "C++".to_string(),
+            },
+        },
+        dependencies: None,
+        targets,
+        description: None,
+        env: None,
+        vars: None,
+        include: None,
+        options: None,
+        configure_files: None,
+        checks: None,
+        commands: None,
+        option_values: Default::default(),
+    };
+    fs::write(&path, convert::serialize(&config, format)?)?;
+    // Сгенерированный конфиг должен сразу загружаться без ошибок
+    config::load_config(&path.to_string_lossy(), &config::LoadOptions::default())?;
+    info!("Created {}", path.display());
+    // Без --config сборка берёт первый найденный WORKSPACE; если это не новый файл, подсказываем
+    if config::find_workspace(Path::new("")).as_ref() != Some(&path) {
+        info!("Build it with: constructor --config {}", path.display());
+    }
+    Ok(())
+}
+
+/// `--config` по умолчанию указывает на WORKSPACE.constructor; для YAML и JSON берём
+/// WORKSPACE.yaml и WORKSPACE.json.
+fn output_path(config_path: &str, format: Option<Format>) -> Result<(PathBuf, Format)> {
+    let detected = Format::from_path(config_path);
+    let format = format.or(detected).unwrap_or(Format::Toml);
+    if detected == Some(format) {
+        return Ok((PathBuf::from(config_path), format));
+    }
+    if config_path == config::DEFAULT_WORKSPACE_FILE_NAME {
+        return Ok((PathBuf::from(format!("WORKSPACE.{}", format.extension())), format));
+    }
+    match format {
+        Format::Toml => anyhow::bail!("{}: a TOML config must have a .toml or .constructor extension", config_path),
+        Format::Yaml => anyhow::bail!("{}: a YAML config must have a .yaml or .yml extension", config_path),
+        Format::Json => anyhow::bail!("{}: a JSON config must have a .json extension", config_path),
+    }
+}
+
+fn target(name: &str, kind: TargetKind, sources: Vec<String>, language: Language) -> TargetConfig {
+    TargetConfig {
+        name: name.to_string(),
+        out_dir: "build".to_string(),
+        kind,
+        sources,
+        includes: None,
+        defines: None,
+        linker_flags: None,
+        compiler_flags: Some(vec!["-Wall".to_string(), "-O2".to_string()]),
+        frameworks: None,
+        os_target: host_os(),
+        compiler: language.compiler().to_string(),
+        pre_build_scripts: None,
+        post_build_scripts: None,
+        env: None,
+        working_dir: None,
+        custom_output: None,
+        extra_steps: None,
+        enabled: None,
+        description: None,
+        deps: None,
+        public_includes: None,
+        public_defines: None,
+        public_link_libraries: None,
+        when: None,
+        resources: None,
+        project_defines: None,
+    }
+}
+
+fn host_os() -> OsTarget {
+    match std::env::consts::OS {
+        "macos" => OsTarget::Macos,
+        "windows" => OsTarget::Windows,
+        _ => OsTarget::Linux,
+    }
+}
+
+/// Исходники языка в директории, рекурсивно и в алфавитном порядке.
+fn find_sources(dir: &str, language: Language) -> Result<Vec<String>> {
+    let mut sources = vec![];
+    for extension in language.extensions() {
+        for entry in glob::glob(&format!("{}/**/*.{}", dir, extension))? {
+            sources.push(entry?.to_string_lossy().to_string());
+        }
+    }
+    sources.sort();
+    Ok(sources)
+}
+
+fn write_samples(name: &str, language: Language, is_library: bool) -> Result<Vec<String>> {
+    let (extension, header_extension) = match language {
+        Language::C => ("c", "h"),
+        Language::Cpp => ("cpp", "hpp"),
+    };
+    if !is_library {
+        let source = format!("src/main.{}", extension);
+        let content = match language {
+            Language::C => format!(
+                "#include <stdio.h>\n\nint main(void)\n{{\n    printf(\"Hello from {}!\\n\");\n    return 0;\n}}\n",
+                name
+            ),
+            Language::Cpp => format!(
+                "#include <iostream>\n\nint main()\n{{\n    std::cout << \"Hello from {}!\" << std::endl;\n    return 0;\n}}\n",
+                name
+            ),
+        };
+        write_new(&source, &content)?;
+        return Ok(vec![source]);
+    }
+    let header = format!("include/{}.{}", name, header_extension);
+    let source = format!("src/{}.{}", name, extension);
+    let (header_content, source_content) = match language {
+        Language::C => (
+            format!("#pragma once\n\nint {}_answer(void);\n", name),
+            format!("#include \"{}.h\"\n\nint {}_answer(void)\n{{\n    return 42;\n}}\n", name, name),
+        ),
+        Language::Cpp => (
+            format!("#pragma once\n\nnamespace {} {{\n\nint answer();\n\n}}\n", name),
+            format!("#include \"{}.hpp\"\n\nnamespace {} {{\n\nint answer()\n{{\n    return 42;\n}}\n\n}}\n", name, name),
+        ),
+    };
+    write_new(&header, &header_content)?;
+    write_new(&source, &source_content)?;
+    Ok(vec![source])
+}
+
+fn write_test_sample(name: &str, language: Language, is_library: bool) -> Result<String> {
+    let (source, content) = match (language, is_library) {
+        (Language::C, true) => (
+            "tests/test_main.c",
+            format!("#include \"{}.h\"\n\nint main(void)\n{{\n    return {}_answer() == 42 ? 0 : 1;\n}}\n", name, name),
+        ),
+        (Language::Cpp, true) => (
+            "tests/test_main.cpp",
+            format!("#include \"{}.hpp\"\n\nint main()\n{{\n    return {}::answer() == 42 ? 0 : 1;\n}}\n", name, name),
+        ),
+        (Language::C, false) => ("tests/test_main.c", "int main(void)\n{\n    return 0;\n}\n".to_string()),
+        (Language::Cpp, false) => ("tests/test_main.cpp", "int main()\n{\n    return 0;\n}\n".to_string()),
+    };
+    write_new(source, &content)?;
+    Ok(source.to_string())
+}
+
+/// Создаёт файл-пример; существующие файлы не трогает.
+fn write_new(path: &str, content: &str) -> Result<()> {
+    let path = Path::new(path);
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    info!("Created {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use crate::config::{BuildConfig, OptionValue};
+use crate::options;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+/// Во сколько уровней пользовательские переменные могут ссылаться друг на друга.
+const MAX_DEPTH: usize = 16;
+
+/// Значения, доступные как `${...}` в строках конфига.
+#[derive(Debug, Clone, Default)]
+pub struct Context
+{
+    /// `project.name`, `project.version`, `target.name`, `profile`, `options.*` и пользовательские [vars]
+    values: HashMap<String, String>,
+}
+
+impl Context {
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    /// Подставляет переменные в строку. `$${` даёт литерал `${`.
+    pub fn expand(&self, input: &str, field: &str) -> Result<String> {
+        self.expand_depth(input, field, 0)
+    }
+
+    fn expand_depth(&self, input: &str, field: &str, depth: usize) -> Result<String> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("variables in {} reference each other recursively", field);
+        }
+        let mut result = String::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            if let Some(escaped) = tail.strip_prefix("$${") {
+                result.push_str("${");
+                rest = escaped;
+            } else if let Some(body) = tail.strip_prefix("${") {
+                let end = body
+                    .find('}')
+                    .ok_or_else(|| anyhow::anyhow!("unterminated '${{' in {}", field))?;
+                let name = &body[..end];
+                result.push_str(&self.lookup(name, field, depth)?);
+                rest = &body[end + 1..];
+            } else {
+                result.push('$');
+                rest = &tail[1..];
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    fn lookup(&self, name: &str, field: &str, depth: usize) -> Result<String> {
+        if let Some(var) = name.strip_prefix("env:") {
+            return std::env::var(var)
+                .map_err(|_| anyhow::anyhow!("environment variable '{}' used in {} is not set", var, field));
+        }
+        match self.values.get(name) {
+            // Пользовательские переменные сами могут содержать ссылки
+            Some(value) => self.expand_depth(value, field, depth + 1),
+            None => Err(anyhow::anyhow!("undefined variable '${{{}}}' in {}", name, field)),
+        }
+    }
+
+    fn expand_value(&self, value: &mut toml::Value, field: &str) -> Result<()> {
+        match value {
+            toml::Value::String(text) => *text = self.expand(text, field)?,
+            toml::Value::Array(items) => {
+                for (index, item) in items.iter_mut().enumerate() {
+                    self.expand_value(item, &format!("{}[{}]", field, index))?;
+                }
+            },
+            toml::Value::Table(table) => {
+                for (key, item) in table.iter_mut() {
+                    self.expand_value(item, &format!("{}.{}", field, key))?;
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// Раскрывает `${project.name}`, `${project.version}`, `${target.name}`, `${profile}`,
+/// `${env:NAME}`, `${options.NAME}` и пользовательские `[vars]` во всех строковых полях конфига.
+pub fn interpolate(config: BuildConfig, profile: &str, option_values: &BTreeMap<String, OptionValue>) -> Result<BuildConfig> {
+    let mut context = Context::default();
+    context.set("profile", profile);
+    for (name, value) in config.vars.iter().flatten() {
+        context.set(name, value);
+    }
+    for (name, value) in option_values {
+        context.set(&format!("{}{}", options::PREFIX, name), &value.to_string());
+    }
+
+    let mut root = toml::Value::try_from(&config)?;
+    let table = root
+        .as_table_mut()
+        .ok_or_else(|| anyhow::anyhow!("config is not a table"))?;
+    // Значения [vars] раскрываются при подстановке, сами они и [options] остаются как есть
+    table.remove("vars");
+    table.remove("options");
+
+    // Поля project могут ссылаться на vars, env и profile, но не на самих себя
+    if let Some(project) = table.get_mut("project") {
+        context.expand_value(project, "project")?;
+    }
+    for field in ["name", "version", "language"] {
+        let value = table
+            .get("project")
+            .and_then(|project| project.get(field))
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+        context.set(&format!("project.{}", field), &value);
+    }
+
+    for (key, value) in table.iter_mut() {
+        match key.as_str() {
+            "project" => {},
+            "targets" => {
+                for (index, target) in value.as_array_mut().into_iter().flatten().enumerate() {
+                    let mut target_context = context.clone();
+                    let name = match target.get("name").and_then(|name| name.as_str()) {
+                        Some(name) => context.expand(name, &format!("targets[{}].name", index))?,
+                        None => String::new(),
+                    };
+                    target_context.set("target.name", &name);
+                    let label = if name.is_empty() { format!("targets[{}]", index) } else { format!("targets.{}", name) };
+                    target_context.expand_value(target, &label)?;
+                }
+            },
+            _ => context.expand_value(value, key)?,
+        }
+    }
+
+    let mut expanded: BuildConfig = root.try_into()?;
+    expanded.vars = config.vars;
+    expanded.options = config.options;
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(values: &[(&str, &str)]) -> Context {
+        let mut context = Context::default();
+        for (name, value) in values {
+            context.set(name, value);
+        }
+        context
+    }
+
+    fn config(toml: &str) -> BuildConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn expands_variables() {
+        let context = context(&[("profile", "release"), ("project.name", "demo")]);
+        assert_eq!(context.expand("build/${profile}/${project.name}", "out_dir").unwrap(), "build/release/demo");
+        assert_eq!(context.expand("no variables", "out_dir").unwrap(), "no variables");
+    }
+
+    #[test]
+    fn keeps_escaped_and_lone_dollars() {
+        let context = context(&[("profile", "debug")]);
+        assert_eq!(context.expand("$${profile}", "run").unwrap(), "${profile}");
+        assert_eq!(context.expand("cost $5 and $HOME", "run").unwrap(), "cost $5 and $HOME");
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(Context::default().expand("${env:PATH}", "env").unwrap(), path);
+        let error = Context::default().expand("${env:CONSTRUCTOR_TEST_UNSET}", "targets.app.includes").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "environment variable 'CONSTRUCTOR_TEST_UNSET' used in targets.app.includes is not set"
+        );
+    }
+
+    #[test]
+    fn expands_nested_user_variables() {
+        let context = context(&[("root", "/opt/${name}"), ("name", "sdk")]);
+        assert_eq!(context.expand("${root}/include", "includes").unwrap(), "/opt/sdk/include");
+    }
+
+    #[test]
+    fn rejects_bad_references() {
+        let context = context(&[("a", "${b}"), ("b", "${a}")]);
+        let error = context.expand("${a}", "vars").unwrap_err();
+        assert_eq!(error.to_string(), "variables in vars reference each other recursively");
+        let error = context.expand("${missing}", "out_dir").unwrap_err();
+        assert_eq!(error.to_string(), "undefined variable '${missing}' in out_dir");
+        let error = context.expand("${profile", "out_dir").unwrap_err();
+        assert_eq!(error.to_string(), "unterminated '${' in out_dir");
+    }
+
+    #[test]
+    fn interpolates_config() {
+        let config = config(
+            r#"
+            [project]
+            name = "demo-${suffix}"
+            version = "1.2"
+            language = "C"
+
+            [vars]
+            suffix = "core"
+            out = "build/${profile}"
+
+            [options]
+            backend = { type = "string", default = "openssl" }
+
+            [[targets]]
+            name = "${project.name}-app"
+            out_dir = "${out}"
+            kind = "executable"
+            sources = ["src/${target.name}.c"]
+            defines = ["VERSION=${project.version}", "BACKEND=${options.backend}"]
+            os_target = "linux"
+            compiler = "gcc"
+            "#,
+        );
+        let values = BTreeMap::from([("backend".to_string(), OptionValue::String("mbedtls".to_string()))]);
+        let expanded = interpolate(config, "release", &values).unwrap();
+        assert_eq!(expanded.project.name, "demo-core");
+        let target = &expanded.targets[0];
+        assert_eq!(target.name, "demo-core-app");
+        assert_eq!(target.out_dir, "build/release");
+        assert_eq!(target.sources, vec!["src/demo-core-app.c".to_string()]);
+        assert_eq!(target.defines.as_deref().unwrap(), ["VERSION=1.2", "BACKEND=mbedtls"]);
+        // [vars] остаются нераскрытыми
+        assert_eq!(expanded.vars.unwrap()["out"], "build/${profile}");
+    }
+
+    #[test]
+    fn names_target_in_errors() {
+        let config = config(
+            r#"
+            [project]
+            name = "demo"
+            version = "1.0"
+            language = "C"
+
+            [[targets]]
+            name = "app"
+            out_dir = "${nowhere}"
+            kind = "executable"
+            sources = ["main.c"]
+            os_target = "linux"
+            compiler = "gcc"
+            "#,
+        );
+        let error = interpolate(config, "debug", &BTreeMap::new()).unwrap_err();
+        assert_eq!(error.to_string(), "undefined variable '${nowhere}' in targets.app.out_dir");
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, json};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Формат логов: текст для людей или JSON, одно событие на строку.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Поля событий, по которым в тексте ставится префикс: таргет или зависимость,
+/// а для общих шагов — их стадия (`checks`, `commands`).
+const PREFIX_KEYS: [&str; 2] = ["target", "stage"];
+
+/// Сколько последних строк stderr упавшей команды показывается с `-q`, когда её вывод скрыт.
+const FAILED_OUTPUT_LINES: usize = 50;
+
+/// Кому принадлежит вывод дочернего процесса: таргету или зависимости (`target`)
+/// или общему шагу (`stage`). Соответствует полям из `PREFIX_KEYS`.
+#[derive(Debug, Clone, Copy)]
+pub enum Prefix<'a> {
+    Target(&'a str),
+    Stage(&'a str),
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+    /// Флаги, с которыми запущен логгер, для вложенных запусков constructor
+    args: Vec<String>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Настраивает логирование: по умолчанию Info, `-v` — Debug (команды сборки),
+/// `-vv` — Trace, `-q` — только предупреждения и ошибки. Логи пишутся в stderr,
+/// чтобы stdout оставался для вывода команд вроде `schema`.
+pub fn init(verbose: u8, quiet: bool, format: LogFormat) -> Result<()> {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let mut args = vec![];
+    if quiet {
+        args.push("-q".to_string());
+    } else if verbose > 0 {
+        args.push(format!("-{}", "v".repeat(verbose as usize)));
+    }
+    if format == LogFormat::Json {
+        args.push("--log-format=json".to_string());
+    }
+    let logger = LOGGER.get_or_init(|| Logger { level, format, args });
+    log::set_logger(logger).map_err(|e| anyhow::anyhow!("Failed to set up logging: {}", e))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Флаги логирования, которые передаются вложенному constructor (сборка локальных зависимостей),
+/// чтобы его вывод был в том же формате и с той же подробностью.
+pub fn forwarded_args() -> Vec<String> {
+    LOGGER.get().map(|logger| logger.args.clone()).unwrap_or_default()
+}
+
+/// Запускает команду и передаёт её stdout и stderr в лог построчно с префиксом `prefix`,
+/// чтобы вывод git, make, cmake и скриптов не ломал ни текстовый формат, ни поток JSON-событий.
+/// Строки идут на уровне Info; если команда упала, а Info скрыт (`-q`), хвост stderr
+/// выводится как предупреждения.
+pub fn run_command(command: &mut Command, prefix: Prefix) -> std::io::Result<ExitStatus> {
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_lines = thread::scope(|scope| {
+        scope.spawn(|| forward(stdout, prefix, "stdout"));
+        forward(stderr, prefix, "stderr")
+    });
+    let status = child.wait()?;
+    if !status.success() && !log::log_enabled!(Level::Info) {
+        let skip = stderr_lines.len().saturating_sub(FAILED_OUTPUT_LINES);
+        for line in &stderr_lines[skip..] {
+            log_line(Level::Warn, prefix, "stderr", line);
+        }
+    }
+    Ok(status)
+}
+
+/// Выводит уже перехваченный вывод команды построчно на уровне Info.
+pub fn log_output(prefix: Prefix, stream: &str, output: &[u8]) {
+    for line in String::from_utf8_lossy(output).lines() {
+        log_line(Level::Info, prefix, stream, line);
+    }
+}
+
+/// Читает поток построчно, пишет строки в лог и возвращает их.
+fn forward(stream: impl Read, prefix: Prefix, name: &str) -> Vec<String> {
+    let mut lines = vec![];
+    for line in BufReader::new(stream).split(b'\n') {
+        let Ok(line) = line else { break };
+        let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+        log_line(Level::Info, prefix, name, &line);
+        lines.push(line);
+    }
+    lines
+}
+
+fn log_line(level: Level, prefix: Prefix, stream: &str, line: &str) {
+    match prefix {
+        Prefix::Target(name) => log::log!(level, target = name, stream = stream; "{}", line),
+        Prefix::Stage(stage) => log::log!(level, stage = stage, stream = stream; "{}", line),
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        let line = match self.format {
+            LogFormat::Text => {
+                let prefix: String = PREFIX_KEYS
+                    .iter()
+                    .find_map(|key| fields.0.get(*key))
+                    .map(|value| format!("[{}] ", value.as_str().unwrap_or_default()))
+                    .unwrap_or_default();
+                let level = match record.level() {
+                    Level::Info => "",
+                    Level::Warn => "warning: ",
+                    Level::Error => "error: ",
+                    Level::Debug => "debug: ",
+                    Level::Trace => "trace: ",
+                };
+                format!("{}{}{}", level, prefix, record.args())
+            },
+            LogFormat::Json => {
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+                let mut event = Map::new();
+                event.insert("time".to_string(), json!(time));
+                event.insert("level".to_string(), json!(record.level().as_str().to_lowercase()));
+                event.insert("message".to_string(), json!(record.args().to_string()));
+                event.extend(fields.0);
+                serde_json::Value::Object(event).to_string()
+            },
+        };
+        // Строка пишется целиком, чтобы вывод параллельных таргетов не перемешивался внутри строки
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+#[derive(Default)]
+struct Fields(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = match (value.to_bool(), value.to_i64(), value.to_f64()) {
+            (Some(value), _, _) => json!(value),
+            (_, Some(value), _) => json!(value),
+            (_, _, Some(value)) => json!(value),
+            _ => json!(value.to_string()),
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
@@ -1,43 +1,231 @@
+mod archive;
+mod cache;
+mod checks;
+mod commands;
 mod config;
+mod configure;
+mod convert;
+mod depbuild;
+mod diagnostics;
 mod builder;
+mod edit;
+mod init;
+mod interpolate;
+mod logging;
+mod merge;
+mod objects;
+mod options;
+mod platform;
+mod resources;
+mod timing;
+mod usage;
+mod vendor;
 
 use anyhow::Result;
-use clap::Parser;
-use log::info;
+use clap::{ArgAction, Parser, Subcommand};
+use log::{error, info};
+use std::path::Path;
+use std::process::ExitCode;
 
 use crate::builder::DefaultBuilder;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long, default_value="WORKSPACE.constructor")]
-    config: String,
+    /// Основной конфиг; повторные --config накладываются поверх него по порядку.
+    /// По умолчанию — первый из WORKSPACE.{constructor,toml,yaml,yml,json} в текущей директории
+    #[arg(short, long, global = true)]
+    config: Vec<String>,
 
     #[arg(long)]
     clean: bool,
 
-    #[arg(long)]
+    #[arg(long, global = true)]
     force: bool,
 
     #[arg(long)]
     makefile: bool,
+
+    /// Показать состояние кеша и команды, которые были бы выполнены, ничего не запуская и не записывая
+    #[arg(long, conflicts_with_all = ["clean", "makefile"])]
+    dry_run: bool,
+
+    /// Для каждого пересобираемого таргета показать, какой файл или какая настройка изменились
+    #[arg(long, conflicts_with_all = ["clean", "makefile"])]
+    explain: bool,
+
+    /// Записать время шагов сборки в файл Chrome trace event (Perfetto, chrome://tracing)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["clean", "makefile", "dry_run"])]
+    trace: Option<String>,
+
+    /// sarif — вдобавок к выводу записать диагностики компилятора в SARIF-файл
+    #[arg(long, value_enum, default_value = "text", conflicts_with_all = ["clean", "makefile"])]
+    diagnostics_format: diagnostics::DiagnosticsFormat,
+
+    /// Куда записать SARIF, "-" — в stdout
+    #[arg(long, value_name = "FILE", default_value = diagnostics::DEFAULT_SARIF_FILE)]
+    diagnostics_output: String,
+
+    /// Не обращаться к сети: зависимости берутся только из deps/ или vendor-директории
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Директория с завендоренными зависимостями
+    #[arg(long, default_value = builder::DEFAULT_VENDOR_DIR, global = true)]
+    vendor_dir: String,
+
+    /// Профиль сборки, доступен в конфиге как ${profile}
+    #[arg(long, default_value = config::DEFAULT_PROFILE, global = true)]
+    profile: String,
+
+    /// Значение опции сборки из [options], например --set enable_ssl=true
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_option_assignment, global = true)]
+    set: Vec<(String, String)>,
+
+    /// Подробнее: -v добавляет команды сборки, -vv — всё остальное
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Только предупреждения и ошибки
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// json — одно событие на строку, для CI и других программ
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    log_format: logging::LogFormat,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Копирует все зависимости на зафиксированных ревизиях в vendor-директорию
+    Vendor,
+    /// Показывает опции сборки с текущими значениями и описаниями
+    Options,
+    /// Создаёт WORKSPACE с примером таргета; существующий конфиг перезаписывается только с --force
+    Init(init::InitArgs),
+    /// Добавляет и меняет таргеты в WORKSPACE, сохраняя комментарии и оформление
+    Target {
+        #[command(subcommand)]
+        command: edit::TargetCommand,
+    },
+    /// Конвертирует конфиг в другой формат без потери полей (комментарии переносятся только в TOML)
+    Convert(convert::ConvertArgs),
+    /// Переписывает конфиг в каноническом порядке полей; с --check только проверяет
+    Fmt(convert::FmtArgs),
+    /// Печатает JSON Schema для WORKSPACE-файлов (автодополнение и проверка в редакторах)
+    Schema,
+    /// Добавляет зависимости в WORKSPACE, сохраняя комментарии и оформление
+    Dep {
+        #[command(subcommand)]
+        command: edit::DepCommand,
+    },
+    /// Собирает и копирует артефакты таргетов: исполняемые файлы в <prefix>/bin, библиотеки в <prefix>/lib
+    Install {
+        #[arg(long, default_value = builder::DEFAULT_INSTALL_PREFIX)]
+        prefix: String,
+
+        /// Какие таргеты устанавливать, по умолчанию все собранные
+        targets: Vec<String>,
+    },
+    /// Собирает и запускает исполняемый таргет; аргументы программы передаются после --
+    Run {
+        /// По умолчанию единственный исполняемый таргет
+        target: Option<String>,
+
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+}
+
+fn parse_option_assignment(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", value))
 }
 
-fn main() -> Result<()>
+fn main() -> ExitCode
 {
-    simple_logger::init_with_level(log::Level::Info)?;
     let args = Args::parse();
+    if let Err(e) = logging::init(args.verbose, args.quiet, args.log_format) {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    // Ошибка тоже идёт через лог, чтобы в --log-format json она была событием
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:#}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run(args: &Args) -> Result<()>
+{
+    // init создаёт новый конфиг, поэтому ищем существующий только для остальных команд
+    let discovered = match (&args.command, args.config.is_empty()) {
+        (Some(Commands::Init(_)), _) | (_, false) => None,
+        (_, true) => config::find_workspace(Path::new("")).map(|path| path.to_string_lossy().to_string()),
+    };
+    let default_config = discovered.unwrap_or_else(|| config::DEFAULT_WORKSPACE_FILE_NAME.to_string());
+    let (config_path, overlays) = args.config.split_first().unwrap_or((&default_config, &[]));
+    let load_options = config::LoadOptions {
+        profile: args.profile.clone(),
+        overlays: overlays.to_vec(),
+        set: args.set.clone(),
+    };
+
+    if args.dry_run && args.command.is_some() {
+        anyhow::bail!("--dry-run applies only to the build itself, not to subcommands");
+    }
+
+    if let Some(Commands::Init(init_args)) = &args.command {
+        return init::run(init_args, config_path, args.force);
+    }
+
+    match &args.command {
+        Some(Commands::Target { command }) => return edit::run_target(command, config_path),
+        Some(Commands::Dep { command }) => return edit::run_dep(command, config_path),
+        Some(Commands::Convert(convert_args)) => return convert::run_convert(convert_args, config_path, args.force),
+        Some(Commands::Fmt(fmt_args)) => return convert::run_fmt(fmt_args, config_path, args.force),
+        Some(Commands::Schema) => {
+            println!("{}", config::json_schema()?);
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    if let Some(Commands::Options) = args.command {
+        let config = config::load_config(config_path, &load_options)?;
+        options::print(config.options.as_ref(), &config.option_values);
+        return Ok(());
+    }
+
+    if let Some(Commands::Vendor) = args.command {
+        info!("Vendoring dependencies into {}...", args.vendor_dir);
+        let config = config::load_config(config_path, &load_options)?;
+        let builder = builder::Builder::new(config, args.force)
+            .with_offline(args.offline, &args.vendor_dir)
+            .with_load_options(&load_options);
+        builder.vendor(&args.vendor_dir)?;
+        return Ok(());
+    }
 
     if args.clean {
         info!("Cleaning build artifacts...");
-        let config = config::load_config(&args.config)?;
+        let config = config::load_config(config_path, &load_options)?;
         let builder = builder::Builder::new(config, args.force);
         builder.clean_cache()?;
         return Ok(());
     }
 
-    info!("Loading configuration from {}...", args.config);
-    let config = config::load_config(&args.config)?;
+    let config_files: Vec<&str> = std::iter::once(config_path).chain(overlays).map(String::as_str).collect();
+    info!("Loading configuration from {}...", config_files.join(", "));
+    let config = config::load_config(config_path, &load_options)?;
 
     if args.makefile {
         let builder = builder::Builder::new(config, args.force);
@@ -45,10 +233,30 @@ fn main() -> Result<()>
         return Ok(());
     }
 
-    info!("Loading configuration from {}...", args.config);
-    let builder = builder::Builder::new(config, args.force);
-    builder.build()?;
+    let builder = builder::Builder::new(config, args.force)
+        .with_offline(args.offline, &args.vendor_dir)
+        .with_load_options(&load_options)
+        .with_dry_run(args.dry_run)
+        .with_explain(args.explain);
+    // Отчёт о времени и диагностики нужны и тогда, когда сборка упала
+    let result = builder.build();
+    let report = builder.report_timings(args.trace.as_deref());
+    let sarif = match args.diagnostics_format {
+        diagnostics::DiagnosticsFormat::Sarif => builder.write_diagnostics(&args.diagnostics_output),
+        diagnostics::DiagnosticsFormat::Text => Ok(()),
+    };
+    result?;
+    report?;
+    sarif?;
 
+    if args.dry_run {
+        info!("Dry run completed, nothing was executed or written.");
+        return Ok(());
+    }
     info!("Build completed successfully!");
-    Ok(())
+    match &args.command {
+        Some(Commands::Install { prefix, targets }) => builder.install(prefix, targets),
+        Some(Commands::Run { target, args }) => builder.run(target.as_deref(), args),
+        _ => Ok(()),
+    }
 }
@@ -0,0 +1,320 @@
+use crate::config::{self, BuildConfig, IncludeFile};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Секции верхнего уровня, элементы которых объединяются по полю `name`,
+/// и как называть их элементы в ошибках.
+const NAMED_SECTIONS: &[(&str, &str)] = &[("targets", "target"), ("dependencies", "dependency"), ("commands", "command")];
+
+/// Конфиг в процессе сборки из основного файла, include-файлов и оверлеев.
+///
+/// Include добавляет новые таргеты, зависимости и команды в порядке шаблонов, а файлы одного шаблона —
+/// в алфавитном порядке; повторное определение имени — ошибка. Оверлей переопределяет поля:
+/// таблицы сливаются рекурсивно, таргеты, зависимости и команды сопоставляются по имени,
+/// остальные значения (в том числе массивы) заменяются целиком.
+pub struct Merged
+{
+    root: toml::Table,
+    /// "target 'app'" -> файл, в котором он определён
+    origins: HashMap<String, String>,
+    /// Уже подключённые файлы, чтобы один файл не попал в конфиг дважды
+    visited: HashSet<PathBuf>,
+}
+
+impl Merged {
+    pub fn new(path: &str, config: &BuildConfig) -> Result<Self> {
+        let mut merged = Self {
+            root: toml::Table::try_from(config)?,
+            origins: HashMap::new(),
+            visited: HashSet::new(),
+        };
+        merged.visited.insert(fs::canonicalize(path)?);
+        for (section, kind) in NAMED_SECTIONS {
+            for item in merged.root.get(*section).and_then(|items| items.as_array()).into_iter().flatten() {
+                if let Some(name) = item_name(item) {
+                    merged.origins.entry(format!("{} '{}'", kind, name)).or_insert_with(|| path.to_string());
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Подключает файлы по шаблонам из `include` файла `from`; пути считаются от его директории.
+    pub fn include_all(&mut self, from: &str, patterns: &[String]) -> Result<()> {
+        let base = Path::new(from).parent().unwrap_or(Path::new(""));
+        for pattern in patterns {
+            let full = base.join(pattern).to_string_lossy().to_string();
+            let mut files = glob::glob(&full)
+                .map_err(|e| anyhow::anyhow!("{}: invalid include pattern '{}': {}", from, pattern, e))?
+                .collect::<Result<Vec<_>, _>>()?;
+            files.sort();
+            if files.is_empty() && !pattern.contains(['*', '?', '[']) {
+                anyhow::bail!("{}: included file '{}' does not exist", from, full);
+            }
+            for file in files {
+                self.include_file(&file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn include_file(&mut self, file: &Path) -> Result<()> {
+        if !self.visited.insert(fs::canonicalize(file)?) {
+            return Ok(());
+        }
+        let path = file.to_string_lossy().to_string();
+        let included: IncludeFile = config::parse_file(&path)?;
+        let table = toml::Table::try_from(&included)?;
+        for (section, kind) in NAMED_SECTIONS {
+            let Some(toml::Value::Array(items)) = table.get(*section) else {
+                continue;
+            };
+            for item in items {
+                let key = format!("{} '{}'", kind, item_name(item).unwrap_or_default());
+                if let Some(other) = self.origins.insert(key.clone(), path.clone()) {
+                    anyhow::bail!("{}: {} is already defined in {}", path, key, other);
+                }
+                section_items(&mut self.root, section).push(item.clone());
+            }
+        }
+        self.include_all(&path, included.include.as_deref().unwrap_or_default())
+    }
+
+    /// Накладывает файл-оверлей поверх текущего конфига.
+    pub fn apply_overlay(&mut self, path: &str) -> Result<()> {
+        let overlay: toml::Table = config::parse_file(path)?;
+        if overlay.contains_key("include") {
+            anyhow::bail!("{}: 'include' is not supported in overlay files", path);
+        }
+        for (key, value) in overlay {
+            match value {
+                toml::Value::Array(items) if NAMED_SECTIONS.iter().any(|(section, _)| *section == key) => {
+                    for item in items {
+                        let name = item_name(&item)
+                            .ok_or_else(|| anyhow::anyhow!("{}: every entry in '{}' must have a name", path, key))?
+                            .to_string();
+                        let existing = section_items(&mut self.root, &key);
+                        match existing.iter_mut().find(|existing| item_name(existing) == Some(name.as_str())) {
+                            Some(existing) => merge_value(existing, item),
+                            None => existing.push(item),
+                        }
+                    }
+                },
+                value => match self.root.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        self.root.insert(key, value);
+                    },
+                },
+            }
+        }
+        // Проверяем сразу, чтобы ошибка указывала на оверлей, который её вызвал
+        self.to_config().map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+        Ok(())
+    }
+
+    /// Директории файлов, в которых объявлены зависимости: от них считаются пути `location`
+    /// локальных зависимостей, как пути `include` — от директории подключающего файла.
+    pub fn dependency_dirs(&self) -> HashMap<String, PathBuf> {
+        let items = self.root.get("dependencies").and_then(|items| items.as_array());
+        items
+            .into_iter()
+            .flatten()
+            .filter_map(item_name)
+            .filter_map(|name| {
+                let origin = self.origins.get(&format!("dependency '{}'", name))?;
+                Some((name.to_string(), Path::new(origin).parent().unwrap_or(Path::new("")).to_path_buf()))
+            })
+            .collect()
+    }
+
+    pub fn into_config(self, path: &str) -> Result<BuildConfig> {
+        self.to_config().map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+    }
+
+    fn to_config(&self) -> Result<BuildConfig, String> {
+        toml::Value::Table(self.root.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| e.message().trim().to_string())
+    }
+}
+
+fn item_name(item: &toml::Value) -> Option<&str> {
+    item.get("name").and_then(|name| name.as_str())
+}
+
+fn section_items<'a>(root: &'a mut toml::Table, section: &str) -> &'a mut Vec<toml::Value> {
+    let value = root
+        .entry(section)
+        .or_insert_with(|| toml::Value::Array(vec![]));
+    if !value.is_array() {
+        *value = toml::Value::Array(vec![]);
+    }
+    value.as_array_mut().expect("section is an array")
+}
+
+fn merge_value(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = r#"
+include = ["targets/*.toml"]
+
+[project]
+name = "demo"
+version = "1.0"
+language = "C"
+
+[[targets]]
+name = "app"
+out_dir = "build"
+kind = "executable"
+sources = ["main.c"]
+defines = ["A=1", "B=2"]
+os_target = "linux"
+compiler = "gcc"
+"#;
+
+    fn target(name: &str) -> String {
+        format!(
+            "[[targets]]\nname = \"{}\"\nout_dir = \"build\"\nkind = \"staticlib\"\nsources = [\"{}.c\"]\nos_target = \"linux\"\ncompiler = \"gcc\"\n",
+            name, name
+        )
+    }
+
+    /// Временная директория с файлами; возвращает путь к основному WORKSPACE.toml.
+    fn workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("constructor-merge-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir.join("WORKSPACE.toml")
+    }
+
+    fn load(main: &Path) -> Result<Merged> {
+        let path = main.to_string_lossy().to_string();
+        let config: BuildConfig = config::parse_file(&path)?;
+        let mut merged = Merged::new(&path, &config)?;
+        merged.include_all(&path, config.include.as_deref().unwrap_or_default())?;
+        Ok(merged)
+    }
+
+    fn target_names(config: &BuildConfig) -> Vec<&str> {
+        config.targets.iter().map(|target| target.name.as_str()).collect()
+    }
+
+    #[test]
+    fn includes_files_in_sorted_order() {
+        let dependency = "[[dependencies]]\nname = \"mylib\"\nsource = \"local\"\nlocation = \"../mylib\"\n";
+        let main = workspace(
+            "sorted",
+            &[
+                ("WORKSPACE.toml", MAIN),
+                ("targets/b.toml", &target("beta")),
+                ("targets/a.toml", &format!("{}{}", target("alpha"), dependency)),
+            ],
+        );
+        let merged = load(&main).unwrap();
+        assert_eq!(merged.dependency_dirs()["mylib"], main.parent().unwrap().join("targets"));
+        let config = merged.into_config("WORKSPACE.toml").unwrap();
+        assert_eq!(target_names(&config), ["app", "alpha", "beta"]);
+        fs::remove_dir_all(main.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_duplicate_and_missing_includes() {
+        let main = workspace("duplicate", &[("WORKSPACE.toml", MAIN), ("targets/a.toml", &target("app"))]);
+        let error = load(&main).err().unwrap().to_string();
+        let file = main.parent().unwrap().join("targets/a.toml");
+        assert_eq!(error, format!("{}: target 'app' is already defined in {}", file.display(), main.display()));
+        fs::remove_dir_all(main.parent().unwrap()).unwrap();
+
+        let main = workspace("missing", &[("WORKSPACE.toml", &MAIN.replace("targets/*.toml", "extra.toml"))]);
+        let error = load(&main).err().unwrap().to_string();
+        assert!(error.ends_with("extra.toml' does not exist"), "{}", error);
+        fs::remove_dir_all(main.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn overlay_merges_by_name() {
+        let overlay = r#"
+[project]
+version = "2.0"
+
+[[targets]]
+name = "app"
+compiler = "clang"
+defines = ["C=3"]
+
+[[targets]]
+name = "tool"
+out_dir = "build"
+kind = "executable"
+sources = ["tool.c"]
+os_target = "linux"
+compiler = "gcc"
+"#;
+        let main = workspace("overlay", &[("WORKSPACE.toml", MAIN), ("WORKSPACE.local", overlay)]);
+        let mut merged = load(&main).unwrap();
+        merged.apply_overlay(&main.with_file_name("WORKSPACE.local").to_string_lossy()).unwrap();
+        let config = merged.into_config("WORKSPACE.toml").unwrap();
+        assert_eq!(config.project.name, "demo");
+        assert_eq!(config.project.version, "2.0");
+        assert_eq!(target_names(&config), ["app", "tool"]);
+        let app = &config.targets[0];
+        assert_eq!(app.compiler, "clang");
+        assert_eq!(app.sources, ["main.c"]);
+        // Массивы заменяются целиком
+        assert_eq!(app.defines.as_deref().unwrap(), ["C=3"]);
+        fs::remove_dir_all(main.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn overlay_errors_name_the_overlay() {
+        let main = workspace(
+            "overlay-errors",
+            &[
+                ("WORKSPACE.toml", MAIN),
+                ("unnamed.toml", "[[targets]]\ncompiler = \"clang\"\n"),
+                ("include.toml", "include = [\"x.toml\"]\n"),
+                ("invalid.toml", "[[targets]]\nname = \"new\"\ncompiler = \"clang\"\n"),
+            ],
+        );
+        let dir = main.parent().unwrap();
+        for (file, message) in [
+            ("unnamed.toml", "every entry in 'targets' must have a name"),
+            ("include.toml", "'include' is not supported in overlay files"),
+            ("invalid.toml", "missing field `out_dir`"),
+        ] {
+            let path = dir.join(file).to_string_lossy().to_string();
+            let mut merged = load(&main).unwrap();
+            let error = merged.apply_overlay(&path).err().unwrap().to_string();
+            assert!(error.starts_with(&format!("{}: ", path)), "{}", error);
+            assert!(error.contains(message), "{}", error);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
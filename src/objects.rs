@@ -0,0 +1,150 @@
+use crate::config::TargetConfig;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Объектный файл таргета вместе с depfile, который пишет компилятор (`-MMD -MF`),
+/// и файлом с хешем команды компиляции.
+pub struct ObjectFile
+{
+    pub source: String,
+    pub object: String,
+    pub depfile: String,
+    stamp: String,
+}
+
+impl ObjectFile {
+    /// Объектные файлы всех исходников таргета в `{out_dir}/.obj/{name}/`.
+    pub fn for_target(target: &TargetConfig) -> Vec<Self> {
+        let obj_dir = object_dir(target);
+        target
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| {
+                let stem = Path::new(source).file_stem().unwrap_or_default().to_string_lossy();
+                let object = format!("{}/{}_{}.o", obj_dir, index, stem);
+                Self {
+                    source: source.clone(),
+                    depfile: format!("{}.d", object),
+                    stamp: format!("{}.cmd", object),
+                    object,
+                }
+            })
+            .collect()
+    }
+
+    /// Исходник и заголовки, от которых объект зависел при прошлой компиляции.
+    pub fn inputs(&self) -> Vec<PathBuf> {
+        fs::read_to_string(&self.depfile)
+            .map(|content| parse_depfile(&content))
+            .unwrap_or_default()
+    }
+
+    /// Объект можно не перекомпилировать: команда та же, и ни один из входов
+    /// из depfile не новее объекта.
+    pub fn is_up_to_date(&self, command_hash: u64) -> bool {
+        let recorded = fs::read_to_string(&self.stamp).ok();
+        if recorded.as_deref().map(str::trim) != Some(command_hash.to_string().as_str()) {
+            return false;
+        }
+        let Some(built) = modified(Path::new(&self.object)) else {
+            return false;
+        };
+        let inputs = self.inputs();
+        !inputs.is_empty() && inputs.iter().all(|input| modified(input).is_some_and(|time| time <= built))
+    }
+
+    pub fn record(&self, command_hash: u64) -> Result<()> {
+        fs::write(&self.stamp, format!("{}\n", command_hash))?;
+        Ok(())
+    }
+}
+
+pub fn object_dir(target: &TargetConfig) -> String {
+    format!("{}/.obj/{}", target.out_dir, target.name)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Разбирает Make-правило из depfile: `obj.o: src.c a.h \` с переносами строк,
+/// пробелами и `#` в путях, экранированными `\ ` и `\#`, и `$`, записанным как `$$`.
+fn parse_depfile(content: &str) -> Vec<PathBuf> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let Some((_, prerequisites)) = content.split_once(": ") else {
+        return vec![];
+    };
+    let mut inputs = vec![];
+    let mut current = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | '#')) => {
+                current.extend(chars.next());
+            },
+            '$' if chars.peek() == Some(&'$') => {
+                current.push('$');
+                chars.next();
+            },
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    inputs.push(PathBuf::from(std::mem::take(&mut current)));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        inputs.push(PathBuf::from(current));
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(items: &[&str]) -> Vec<PathBuf> {
+        items.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn parses_single_line() {
+        assert_eq!(parse_depfile("build/a.o: src/a.c src/a.h\n"), paths(&["src/a.c", "src/a.h"]));
+    }
+
+    #[test]
+    fn parses_continuation_lines() {
+        let content = "build/a.o: src/a.c \\\n  src/a.h \\\n  include/util.h\n";
+        assert_eq!(parse_depfile(content), paths(&["src/a.c", "src/a.h", "include/util.h"]));
+        let content = "build/a.o: src/a.c \\\r\n  src/a.h\r\n";
+        assert_eq!(parse_depfile(content), paths(&["src/a.c", "src/a.h"]));
+    }
+
+    #[test]
+    fn parses_escaped_spaces() {
+        // Так gcc -MMD записывает "my dir/a b.h"
+        let content = "build/my\\ dir/a.o: my\\ dir/a.c my\\ dir/a\\ b.h \\\n plain.h\n";
+        assert_eq!(parse_depfile(content), paths(&["my dir/a.c", "my dir/a b.h", "plain.h"]));
+    }
+
+    #[test]
+    fn parses_escaped_dollar_and_hash() {
+        assert_eq!(parse_depfile("a.o: a.c x$$y.h h\\#1.h\n"), paths(&["a.c", "x$y.h", "h#1.h"]));
+    }
+
+    #[test]
+    fn keeps_other_backslashes() {
+        assert_eq!(parse_depfile("a.o: dir\\a.c\n"), paths(&["dir\\a.c"]));
+    }
+
+    #[test]
+    fn ignores_content_without_rule() {
+        assert!(parse_depfile("").is_empty());
+        assert!(parse_depfile("garbage").is_empty());
+        assert!(parse_depfile("a.o:").is_empty());
+    }
+}
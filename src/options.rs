@@ -0,0 +1,174 @@
+use crate::config::{BuildOption, OptionType, OptionValue};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Префикс, под которым опции доступны в `${...}` и в условиях `cfg(...)`.
+pub const PREFIX: &str = "options.";
+
+/// Значения опций: default из конфига, поверх которого применяются `--set name=value`.
+pub fn resolve(
+    declared: Option<&BTreeMap<String, BuildOption>>,
+    set: &[(String, String)],
+) -> Result<BTreeMap<String, OptionValue>> {
+    let mut values = BTreeMap::new();
+    for (name, option) in declared.into_iter().flatten() {
+        check(option, &option.default).map_err(|e| anyhow::anyhow!("option '{}' has an invalid default: {}", name, e))?;
+        values.insert(name.clone(), option.default.clone());
+    }
+    for (name, raw) in set {
+        let Some(option) = declared.and_then(|declared| declared.get(name)) else {
+            let known: Vec<&str> = values.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "--set {}: unknown option '{}' (declared options: {})",
+                name,
+                name,
+                if known.is_empty() { "none".to_string() } else { known.join(", ") }
+            );
+        };
+        let value = parse(option, raw).map_err(|e| anyhow::anyhow!("--set {}={}: {}", name, raw, e))?;
+        values.insert(name.clone(), value);
+    }
+    Ok(values)
+}
+
+fn parse(option: &BuildOption, raw: &str) -> Result<OptionValue> {
+    let value = match option.kind {
+        OptionType::Bool => match raw {
+            "true" | "on" | "yes" | "1" => OptionValue::Bool(true),
+            "false" | "off" | "no" | "0" => OptionValue::Bool(false),
+            _ => anyhow::bail!("expected true or false"),
+        },
+        OptionType::Int => OptionValue::Int(raw.parse().map_err(|_| anyhow::anyhow!("expected an integer"))?),
+        OptionType::String => OptionValue::String(raw.to_string()),
+    };
+    check(option, &value)?;
+    Ok(value)
+}
+
+fn check(option: &BuildOption, value: &OptionValue) -> Result<()> {
+    match (option.kind, value) {
+        (OptionType::Bool, OptionValue::Bool(_)) | (OptionType::Int, OptionValue::Int(_)) => Ok(()),
+        (OptionType::String, OptionValue::String(value)) => match &option.choices {
+            Some(choices) if !choices.contains(value) => {
+                anyhow::bail!("'{}' is not one of: {}", value, choices.join(", "))
+            },
+            _ => Ok(()),
+        },
+        (kind, value) => anyhow::bail!("expected a {} value, got '{}'", kind, value),
+    }
+}
+
+/// Дефайны опций для таргетов с `project_defines = true`: bool — `NAME=1`/`NAME=0`,
+/// int — `NAME=42`, string — строковый литерал `NAME="value"`.
+pub fn defines(declared: Option<&BTreeMap<String, BuildOption>>, values: &BTreeMap<String, OptionValue>) -> Vec<String> {
+    let mut defines = vec![];
+    for (name, option) in declared.into_iter().flatten() {
+        let Some(value) = values.get(name) else { continue; };
+        let define = option.define.clone().unwrap_or_else(|| name.to_uppercase());
+        defines.push(match value {
+            OptionValue::Bool(value) => format!("{}={}", define, *value as u8),
+            OptionValue::Int(value) => format!("{}={}", define, value),
+            OptionValue::String(value) => format!("{}=\"{}\"", define, value.replace('\\', "\\\\").replace('"', "\\\"")),
+        });
+    }
+    defines
+}
+
+/// Вывод команды `constructor options`.
+pub fn print(declared: Option<&BTreeMap<String, BuildOption>>, values: &BTreeMap<String, OptionValue>) {
+    let Some(declared) = declared.filter(|declared| !declared.is_empty()) else {
+        println!("No options declared.");
+        return;
+    };
+    for (name, option) in declared {
+        let value = values.get(name).unwrap_or(&option.default);
+        let changed = if *value == option.default { String::new() } else { format!(" (default: {})", option.default) };
+        println!("{} = {} [{}]{}", name, value, option.kind, changed);
+        if let Some(description) = &option.description {
+            println!("    {}", description);
+        }
+        if let Some(choices) = &option.choices {
+            println!("    choices: {}", choices.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declared() -> BTreeMap<String, BuildOption> {
+        toml::from_str(
+            r#"
+            enable_ssl = { type = "bool", default = false }
+            jobs = { type = "int", default = 4, define = "MAX_JOBS" }
+            backend = { type = "string", default = "openssl", choices = ["openssl", "mbedtls"] }
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn set(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn resolves_defaults_and_overrides() {
+        let declared = declared();
+        let values = resolve(Some(&declared), &[]).unwrap();
+        assert_eq!(values["enable_ssl"], OptionValue::Bool(false));
+        assert_eq!(values["jobs"], OptionValue::Int(4));
+        assert_eq!(values["backend"], OptionValue::String("openssl".to_string()));
+
+        let values = resolve(Some(&declared), &set(&[("enable_ssl", "on"), ("jobs", "-2"), ("backend", "mbedtls")])).unwrap();
+        assert_eq!(values["enable_ssl"], OptionValue::Bool(true));
+        assert_eq!(values["jobs"], OptionValue::Int(-2));
+        assert_eq!(values["backend"], OptionValue::String("mbedtls".to_string()));
+        // Последний --set побеждает
+        let values = resolve(Some(&declared), &set(&[("enable_ssl", "yes"), ("enable_ssl", "0")])).unwrap();
+        assert_eq!(values["enable_ssl"], OptionValue::Bool(false));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let declared = declared();
+        for (name, value, message) in [
+            ("enable_ssl", "maybe", "--set enable_ssl=maybe: expected true or false"),
+            ("jobs", "many", "--set jobs=many: expected an integer"),
+            ("backend", "gnutls", "--set backend=gnutls: 'gnutls' is not one of: openssl, mbedtls"),
+            ("verbose", "1", "--set verbose: unknown option 'verbose' (declared options: backend, enable_ssl, jobs)"),
+        ] {
+            let error = resolve(Some(&declared), &set(&[(name, value)])).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+        let error = resolve(None, &set(&[("jobs", "2")])).unwrap_err();
+        assert_eq!(error.to_string(), "--set jobs: unknown option 'jobs' (declared options: none)");
+    }
+
+    #[test]
+    fn rejects_invalid_defaults() {
+        let mismatched: BTreeMap<String, BuildOption> = toml::from_str(r#"jobs = { type = "int", default = "four" }"#).unwrap();
+        let error = resolve(Some(&mismatched), &[]).unwrap_err();
+        assert_eq!(error.to_string(), "option 'jobs' has an invalid default: expected a int value, got 'four'");
+        let outside: BTreeMap<String, BuildOption> =
+            toml::from_str(r#"backend = { type = "string", default = "none", choices = ["openssl"] }"#).unwrap();
+        let error = resolve(Some(&outside), &[]).unwrap_err();
+        assert_eq!(error.to_string(), "option 'backend' has an invalid default: 'none' is not one of: openssl");
+    }
+
+    #[test]
+    fn builds_defines() {
+        let mut declared = declared();
+        declared.insert(
+            "motd".to_string(),
+            toml::from_str(r#"type = "string"
+default = 'say "hi" \ bye'"#).unwrap(),
+        );
+        let values = resolve(Some(&declared), &set(&[("enable_ssl", "true")])).unwrap();
+        assert_eq!(
+            defines(Some(&declared), &values),
+            ["BACKEND=\"openssl\"", "ENABLE_SSL=1", "MAX_JOBS=4", r#"MOTD="say \"hi\" \\ bye""#]
+        );
+        assert!(defines(None, &values).is_empty());
+    }
+}
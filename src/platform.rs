@@ -0,0 +1,450 @@
+use crate::config::{BuildConfig, OptionValue, OsTarget, TargetConfig};
+use crate::{checks, options};
+use crate::usage::link_argument;
+use anyhow::Result;
+use log::info;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::process::Command;
+
+/// Платформа, под которую собирает тулчейн таргета.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform
+{
+    /// linux, macos, windows или другое имя ОС из триплета
+    pub os: String,
+    /// x86_64, aarch64, x86, arm, riscv64...
+    pub arch: String,
+    /// unix или windows
+    pub family: String,
+    /// Исходный триплет, если его удалось узнать у компилятора
+    pub triple: Option<String>,
+}
+
+impl Platform {
+    /// Платформа, на которой запущен constructor.
+    pub fn host() -> Self {
+        let os = std::env::consts::OS.to_string();
+        let arch = std::env::consts::ARCH.to_string();
+        let family = std::env::consts::FAMILY.to_string();
+        Self { os, arch, family, triple: None }
+    }
+
+    /// Спрашивает у компилятора его целевой триплет (`-dumpmachine`, понимают gcc и clang).
+    /// Компилятор запускается с `env` таргета, как при сборке, чтобы `PATH` из конфига
+    /// выбрал тот же тулчейн. Если компилятор не ответил, считаем, что он собирает под хост.
+    pub fn for_compiler(compiler: &str, flags: &[String], env: &[(String, String)]) -> Self {
+        let output = Command::new(compiler).envs(env.iter().cloned()).args(flags).arg("-dumpmachine").output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let triple = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if triple.is_empty() { Self::host() } else { Self::from_triple(&triple) }
+            },
+            _ => Self::host(),
+        }
+    }
+
+    /// x86_64-linux-gnu, aarch64-apple-darwin23, x86_64-w64-mingw32...
+    pub fn from_triple(triple: &str) -> Self {
+        let arch = match triple.split('-').next().unwrap_or_default() {
+            "arm64" => "aarch64",
+            "i386" | "i486" | "i586" | "i686" => "x86",
+            arch if arch.starts_with("arm") => "arm",
+            arch => arch,
+        };
+        let os = if triple.contains("linux") {
+            "linux"
+        } else if triple.contains("darwin") || triple.contains("apple") || triple.contains("macos") {
+            "macos"
+        } else if triple.contains("mingw") || triple.contains("windows") || triple.contains("cygwin") || triple.contains("msvc") {
+            "windows"
+        } else {
+            // x86_64-unknown-freebsd13.2, arm-none-eabi
+            KNOWN_OS.iter().copied().find(|os| triple.split('-').any(|part| part.starts_with(os))).unwrap_or("unknown")
+        };
+        let family = if os == "windows" { "windows" } else { "unix" };
+        Self { os: os.to_string(), arch: arch.to_string(), family: family.to_string(), triple: Some(triple.to_string()) }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.triple {
+            Some(triple) => write!(f, "{}-{} ({})", self.arch, self.os, triple),
+            None => write!(f, "{}-{}", self.arch, self.os),
+        }
+    }
+}
+
+/// Имена ОС, архитектур и семейств платформ, которые можно использовать в условиях `when`.
+pub const KNOWN_OS: &[&str] = &[
+    "linux", "macos", "windows", "ios", "android", "freebsd", "netbsd", "openbsd", "dragonfly", "solaris",
+    "illumos", "aix", "haiku", "fuchsia", "redox", "wasi", "emscripten", "none",
+];
+pub const KNOWN_ARCH: &[&str] = &[
+    "x86_64", "x86", "aarch64", "arm", "riscv32", "riscv64", "powerpc", "powerpc64", "powerpc64le", "mips", "mipsel",
+    "mips64", "mips64el", "s390x", "sparc64", "loongarch64", "wasm32", "wasm64",
+];
+pub const KNOWN_FAMILY: &[&str] = &["unix", "windows"];
+
+/// Условие из `when`: имя платформы (`linux`, `aarch64`, `unix`) или выражение
+/// `cfg(target_os = "linux", target_arch = "aarch64")` с `all`, `any` и `not`.
+/// Опции сборки и результаты проверок проверяются как `options.enable_ssl` (bool),
+/// `options.backend = "openssl"` и `checks.SIZEOF_LONG = "8"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cfg
+{
+    Name(String),
+    Equal(String, String),
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+}
+
+const CFG_KEYS: &[&str] = &["target_os", "target_arch", "target_family"];
+
+impl Cfg {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if !input.starts_with("cfg(") {
+            if input.is_empty() || !input.chars().all(is_ident_char) {
+                anyhow::bail!("expected a platform name or cfg(...)");
+            }
+            return Ok(Cfg::Name(input.to_string()));
+        }
+        let mut parser = CfgParser { input, pos: 0 };
+        // Верхний cfg(a, b) означает all(a, b)
+        let cfg = parser.expr()?;
+        parser.skip_whitespace();
+        if parser.pos != input.len() {
+            anyhow::bail!("unexpected '{}' after cfg(...)", &input[parser.pos..]);
+        }
+        Ok(cfg)
+    }
+
+    /// `values` — значения из [`condition_values`], ключи вида `options.NAME` и `checks.NAME`.
+    pub fn matches(&self, platform: &Platform, values: &BTreeMap<String, OptionValue>) -> bool {
+        match self {
+            Cfg::Name(name) if is_reference(name) => values.get(name) == Some(&OptionValue::Bool(true)),
+            Cfg::Name(name) => [&platform.os, &platform.arch, &platform.family].contains(&name),
+            Cfg::Equal(key, value) => match key.as_str() {
+                "target_os" => &platform.os == value,
+                "target_arch" => &platform.arch == value,
+                "target_family" => &platform.family == value,
+                key => values.get(key).is_some_and(|actual| &actual.to_string() == value),
+            },
+            Cfg::All(items) => items.iter().all(|item| item.matches(platform, values)),
+            Cfg::Any(items) => items.iter().any(|item| item.matches(platform, values)),
+            Cfg::Not(item) => !item.matches(platform, values),
+        }
+    }
+
+    /// Опции и проверки, на которые ссылается условие: `options.NAME`, `checks.NAME`.
+    pub fn references(&self) -> Vec<&str> {
+        match self {
+            Cfg::Name(name) | Cfg::Equal(name, _) if is_reference(name) => vec![name.as_str()],
+            Cfg::Name(_) | Cfg::Equal(_, _) => vec![],
+            Cfg::All(items) | Cfg::Any(items) => items.iter().flat_map(Cfg::references).collect(),
+            Cfg::Not(item) => item.references(),
+        }
+    }
+
+    /// Имена платформ из условия, которых нет среди известных ОС, архитектур и семейств:
+    /// такое условие никогда не выполнится, обычно это опечатка.
+    pub fn unknown_names(&self) -> Vec<&str> {
+        match self {
+            Cfg::Name(name) if is_reference(name) => vec![],
+            Cfg::Name(name) => {
+                let known = [KNOWN_OS, KNOWN_ARCH, KNOWN_FAMILY].iter().any(|names| names.contains(&name.as_str()));
+                if known { vec![] } else { vec![name.as_str()] }
+            },
+            Cfg::Equal(key, value) => {
+                let known = match key.as_str() {
+                    "target_os" => KNOWN_OS,
+                    "target_arch" => KNOWN_ARCH,
+                    "target_family" => KNOWN_FAMILY,
+                    _ => return vec![],
+                };
+                if known.contains(&value.as_str()) { vec![] } else { vec![value.as_str()] }
+            },
+            Cfg::All(items) | Cfg::Any(items) => items.iter().flat_map(Cfg::unknown_names).collect(),
+            Cfg::Not(item) => item.unknown_names(),
+        }
+    }
+}
+
+struct CfgParser<'a>
+{
+    input: &'a str,
+    pos: usize,
+}
+
+impl CfgParser<'_> {
+    /// Пропускает пробельные символы; позиция всегда остаётся на границе символа,
+    /// в том числе после многобайтовых пробелов вроде U+3000.
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: char) -> bool {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(token) {
+            self.pos += token.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            match rest.chars().next() {
+                Some(c) => anyhow::bail!("unexpected character '{}' at '{}'", c, rest),
+                None => anyhow::bail!("unexpected end of condition"),
+            }
+        }
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        if !self.eat('"') {
+            anyhow::bail!("expected a quoted value at '{}'", &self.input[self.pos..]);
+        }
+        let rest = &self.input[self.pos..];
+        let end = rest.find('"').ok_or_else(|| anyhow::anyhow!("unterminated string"))?;
+        self.pos += end + 1;
+        Ok(rest[..end].to_string())
+    }
+
+    fn expr(&mut self) -> Result<Cfg> {
+        let name = self.ident()?;
+        if self.eat('=') {
+            if !CFG_KEYS.contains(&name.as_str()) && !is_reference(&name) {
+                anyhow::bail!(
+                    "unknown cfg key '{}', expected one of {}, options.<name> or checks.<name>",
+                    name,
+                    CFG_KEYS.join(", ")
+                );
+            }
+            return Ok(Cfg::Equal(name, self.string()?));
+        }
+        if !self.eat('(') {
+            return Ok(Cfg::Name(name));
+        }
+        let mut items = vec![];
+        while !self.eat(')') {
+            items.push(self.expr()?);
+            if self.eat(')') {
+                break;
+            }
+            if !self.eat(',') {
+                anyhow::bail!("expected ',' or ')' at '{}'", &self.input[self.pos..]);
+            }
+        }
+        match name.as_str() {
+            "cfg" | "all" => Ok(Cfg::All(items)),
+            "any" => Ok(Cfg::Any(items)),
+            "not" if items.len() == 1 => Ok(Cfg::Not(Box::new(items.remove(0)))),
+            "not" => anyhow::bail!("not(...) takes exactly one condition"),
+            _ => anyhow::bail!("unknown cfg function '{}', expected all, any or not", name),
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn is_reference(name: &str) -> bool {
+    name.starts_with(options::PREFIX) || name.starts_with(checks::PREFIX)
+}
+
+/// Значения для условий `when`: опции сборки и результаты проверок под своими префиксами.
+pub fn condition_values(
+    option_values: &BTreeMap<String, OptionValue>,
+    check_values: &BTreeMap<String, OptionValue>,
+) -> BTreeMap<String, OptionValue> {
+    let options = option_values.iter().map(|(name, value)| (format!("{}{}", options::PREFIX, name), value.clone()));
+    let checks = check_values.iter().map(|(name, value)| (format!("{}{}", checks::PREFIX, name), value.clone()));
+    options.chain(checks).collect()
+}
+
+/// Компилятор, его флаги и окружение: всё, от чего зависит ответ `-dumpmachine`.
+type Toolchain = (String, Vec<String>, Vec<(String, String)>);
+
+/// Включённые таргеты конфига, которые собираются под платформу своего тулчейна,
+/// с применёнными блоками `when`. Остальные пропускаются с сообщением в логе.
+pub fn resolve_targets(config: &BuildConfig, values: &BTreeMap<String, OptionValue>) -> Result<Vec<TargetConfig>> {
+    let mut platforms: HashMap<Toolchain, Platform> = HashMap::new();
+    let mut resolved = vec![];
+    for target in &config.targets {
+        if let Some(false) = target.enabled { continue; }
+        let flags = target.compiler_flags.clone().unwrap_or_default();
+        let platform = platforms
+            .entry((target.compiler.clone(), flags, config.env_for(Some(target))))
+            .or_insert_with_key(|(compiler, flags, env)| Platform::for_compiler(compiler, flags, env));
+        if !os_matches(target.os_target, platform) {
+            info!(
+                target = target.name.as_str();
+                "Skipping: os_target is {}, but {} builds for {}",
+                target.os_target, target.compiler, platform
+            );
+            continue;
+        }
+        resolved.push(apply_conditionals(target, platform, values)?);
+    }
+    Ok(resolved)
+}
+
+fn os_matches(os_target: OsTarget, platform: &Platform) -> bool {
+    platform.os == os_target.to_string()
+}
+
+/// Добавляет к таргету содержимое подходящих блоков `when` в порядке их ключей;
+/// у результата `when` уже не остаётся.
+pub fn apply_conditionals(
+    target: &TargetConfig,
+    platform: &Platform,
+    values: &BTreeMap<String, OptionValue>,
+) -> Result<TargetConfig> {
+    let mut target = target.clone();
+    let Some(conditionals) = target.when.take() else {
+        return Ok(target);
+    };
+    fn extend(field: &mut Option<Vec<String>>, values: &Option<Vec<String>>) {
+        if let Some(values) = values {
+            field.get_or_insert_with(Vec::new).extend(values.iter().cloned());
+        }
+    }
+    for (condition, block) in &conditionals {
+        let cfg = Cfg::parse(condition)
+            .map_err(|e| anyhow::anyhow!("target '{}': invalid condition '{}': {}", target.name, condition, e))?;
+        if !cfg.matches(platform, values) {
+            continue;
+        }
+        if let Some(sources) = &block.sources {
+            target.sources.extend(sources.iter().cloned());
+        }
+        extend(&mut target.includes, &block.includes);
+        extend(&mut target.defines, &block.defines);
+        extend(&mut target.compiler_flags, &block.compiler_flags);
+        extend(&mut target.linker_flags, &block.linker_flags);
+        extend(&mut target.frameworks, &block.frameworks);
+        let link_libraries = block.link_libraries.as_ref().map(|libs| libs.iter().map(|lib| link_argument(lib)).collect());
+        extend(&mut target.linker_flags, &link_libraries);
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Cfg {
+        Cfg::Name(name.to_string())
+    }
+
+    fn equal(key: &str, value: &str) -> Cfg {
+        Cfg::Equal(key.to_string(), value.to_string())
+    }
+
+    fn linux_x86_64() -> Platform {
+        Platform::from_triple("x86_64-linux-gnu")
+    }
+
+    #[test]
+    fn parses_platform_names() {
+        assert_eq!(Cfg::parse("linux").unwrap(), name("linux"));
+        assert_eq!(Cfg::parse("  options.enable_ssl ").unwrap(), name("options.enable_ssl"));
+    }
+
+    #[test]
+    fn parses_all_any_not() {
+        let cfg = Cfg::parse(r#"cfg(any(target_os = "linux", target_os = "macos"), not(target_arch = "x86"))"#).unwrap();
+        assert_eq!(
+            cfg,
+            Cfg::All(vec![
+                Cfg::Any(vec![equal("target_os", "linux"), equal("target_os", "macos")]),
+                Cfg::Not(Box::new(equal("target_arch", "x86"))),
+            ])
+        );
+        assert_eq!(Cfg::parse("cfg(all(unix, options.enable_ssl))").unwrap(), Cfg::All(vec![Cfg::All(vec![name("unix"), name("options.enable_ssl")])]));
+    }
+
+    #[test]
+    fn parses_key_value_pairs() {
+        assert_eq!(Cfg::parse(r#"cfg(target_family="unix")"#).unwrap(), Cfg::All(vec![equal("target_family", "unix")]));
+        assert_eq!(Cfg::parse(r#"cfg(checks.SIZEOF_LONG = "8")"#).unwrap(), Cfg::All(vec![equal("checks.SIZEOF_LONG", "8")]));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for input in [
+            "",
+            "linux x86",
+            "cfg(",
+            "cfg(linux",
+            r#"cfg(target_os = linux)"#,
+            r#"cfg(target_os = "linux)"#,
+            r#"cfg(target_vendor = "apple")"#,
+            "cfg(maybe(linux))",
+            "cfg(not(linux, unix))",
+            "cfg(linux) extra",
+        ] {
+            assert!(Cfg::parse(input).is_err(), "{:?} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn handles_non_ascii_input() {
+        // U+3000 — пробел, но занимает три байта
+        assert_eq!(
+            Cfg::parse("cfg(\u{3000}target_os\u{3000}=\u{3000}\"linux\"\u{3000})").unwrap(),
+            Cfg::All(vec![equal("target_os", "linux")])
+        );
+        assert_eq!(Cfg::parse(r#"cfg(target_os = "линукс")"#).unwrap(), Cfg::All(vec![equal("target_os", "линукс")]));
+        assert!(Cfg::parse("линукс").is_err());
+        assert!(Cfg::parse("cfg(линукс)").is_err());
+        assert!(Cfg::parse("cfg(linux\u{3000}ü)").is_err());
+    }
+
+    #[test]
+    fn reports_unknown_platform_names() {
+        let unknown = |input: &str| Cfg::parse(input).unwrap().unknown_names().into_iter().map(str::to_string).collect::<Vec<_>>();
+        assert!(unknown("linux").is_empty());
+        assert!(unknown("options.anything").is_empty());
+        assert!(unknown(r#"cfg(any(aarch64, target_os = "macos", target_family = "unix"), checks.X = "1")"#).is_empty());
+        assert_eq!(unknown("linx"), ["linx"]);
+        assert_eq!(unknown(r#"cfg(any(target_os = "mac", not(target_arch = "amd64")))"#), ["mac", "amd64"]);
+    }
+
+    #[test]
+    fn reads_os_from_triple() {
+        assert_eq!(Platform::from_triple("aarch64-apple-darwin23").os, "macos");
+        assert_eq!(Platform::from_triple("x86_64-w64-mingw32").os, "windows");
+        assert_eq!(Platform::from_triple("x86_64-unknown-freebsd13.2").os, "freebsd");
+        assert_eq!(Platform::from_triple("arm-none-eabi").os, "none");
+        assert_eq!(Platform::from_triple("arm-none-eabi").arch, "arm");
+    }
+
+    #[test]
+    fn matches_platform_and_values() {
+        let platform = linux_x86_64();
+        let values = BTreeMap::from([
+            ("options.enable_ssl".to_string(), OptionValue::Bool(true)),
+            ("options.backend".to_string(), OptionValue::String("openssl".to_string())),
+        ]);
+        let matches = |input: &str| Cfg::parse(input).unwrap().matches(&platform, &values);
+        assert!(matches("linux"));
+        assert!(matches("unix"));
+        assert!(!matches("windows"));
+        assert!(matches("options.enable_ssl"));
+        assert!(matches(r#"cfg(all(target_arch = "x86_64", options.backend = "openssl"))"#));
+        assert!(!matches(r#"cfg(not(any(target_os = "linux", target_os = "macos")))"#));
+    }
+}
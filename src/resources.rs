@@ -0,0 +1,211 @@
+use crate::config::TargetConfig;
+use crate::configure::write_if_changed;
+use anyhow::Result;
+use log::info;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Сгенерированные для таргета файлы с ресурсами.
+pub struct Resources
+{
+    /// `{out_dir}/.gen/{name}/{name}_resources.c`
+    pub source: String,
+    /// Директория с `{name}_resources.h`, добавляется в includes таргета
+    pub include_dir: String,
+}
+
+/// Директория со сгенерированными файлами таргета.
+pub fn gen_dir(target: &TargetConfig) -> String {
+    format!("{}/.gen/{}", target.out_dir, target.name)
+}
+
+/// Генерирует C-исходник и заголовок, в которых каждый файл из `resources` доступен как
+/// `const unsigned char <имя>[]` и `const size_t <имя>_size`. Имя — путь к файлу, в котором
+/// всё, кроме букв и цифр, заменено на `_`: `assets/index.html` -> `assets_index_html`.
+/// Файлы перезаписываются, только если изменилось их содержимое, так что `touch` ресурса
+/// без правки не перекомпилирует таргет.
+/// С `dry_run` файлы не пишутся, только сообщается, что они были бы сгенерированы.
+pub fn generate(target: &TargetConfig, dry_run: bool) -> Result<Option<Resources>> {
+    let Some(patterns) = target.resources.as_ref().filter(|patterns| !patterns.is_empty()) else {
+        return Ok(None);
+    };
+    let files = files(target, patterns)?;
+    let mut names = HashSet::new();
+    let mut entries = vec![];
+    for file in files {
+        let name = identifier(&file.to_string_lossy());
+        if !names.insert(name.clone()) {
+            anyhow::bail!("Target '{}': resources {} and another file both map to '{}'", target.name, file.display(), name);
+        }
+        entries.push((file, name));
+    }
+
+    let dir = gen_dir(target);
+    let base_name = format!("{}_resources", identifier(&target.name));
+    let header_path = format!("{}/{}.h", dir, base_name);
+    let source_path = format!("{}/{}.c", dir, base_name);
+
+    let mut header = format!(
+        "/* Generated by constructor from resources of target '{}'. Do not edit. */\n\
+        #pragma once\n\n#include <stddef.h>\n\n#ifdef __cplusplus\nextern \"C\" {{\n#endif\n\n",
+        target.name
+    );
+    for (file, name) in &entries {
+        writeln!(header, "/* {} */", file.display())?;
+        writeln!(header, "extern const unsigned char {}[];", name)?;
+        writeln!(header, "extern const size_t {}_size;", name)?;
+    }
+    header.push_str("\n#ifdef __cplusplus\n}\n#endif\n");
+    let header_changed = if dry_run {
+        fs::read_to_string(&header_path).ok().as_deref() != Some(header.as_str())
+    } else {
+        write_if_changed(Path::new(&header_path), &header)?
+    };
+
+    // Исходник включает свой заголовок: при компиляции как C++ определения получают
+    // внешнюю C-линковку из объявлений
+    let mut source = format!(
+        "/* Generated by constructor from resources of target '{}'. Do not edit. */\n#include \"{}.h\"\n",
+        target.name, base_name
+    );
+    for (file, name) in &entries {
+        let content = fs::read(file)
+            .map_err(|e| anyhow::anyhow!("Target '{}': resource {}: {}", target.name, file.display(), e))?;
+        writeln!(source, "\n/* {} */", file.display())?;
+        writeln!(source, "const unsigned char {}[] = {{", name)?;
+        // Завершающий 0 не входит в размер, но позволяет читать текстовые ресурсы как строку
+        for chunk in content.iter().chain(std::iter::once(&0)).collect::<Vec<_>>().chunks(16) {
+            let bytes: Vec<String> = chunk.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            writeln!(source, "    {},", bytes.join(", "))?;
+        }
+        writeln!(source, "}};")?;
+        writeln!(source, "const size_t {}_size = {};", name, content.len())?;
+    }
+    if dry_run {
+        if header_changed || fs::read_to_string(&source_path).ok().as_deref() != Some(source.as_str()) {
+            info!(target = target.name.as_str(); "[dry-run] generate {} from {} resource(s)", source_path, entries.len());
+        }
+    } else if write_if_changed(Path::new(&source_path), &source)? {
+        info!(target = target.name.as_str(); "Generated {} from {} resource(s)", source_path, entries.len());
+    }
+    Ok(Some(Resources { source: source_path, include_dir: dir }))
+}
+
+/// Файлы ресурсов: пути как есть, glob-шаблоны раскрываются в алфавитном порядке.
+fn files(target: &TargetConfig, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            if !Path::new(pattern).is_file() {
+                anyhow::bail!("Target '{}': resource {} does not exist", target.name, pattern);
+            }
+            files.push(PathBuf::from(pattern));
+            continue;
+        }
+        let mut matched: Vec<PathBuf> = glob::glob(pattern)
+            .map_err(|e| anyhow::anyhow!("Target '{}': invalid resource pattern '{}': {}", target.name, pattern, e))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect();
+        matched.sort();
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+fn identifier(path: &str) -> String {
+    let name: String = path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) { format!("_{}", name) } else { name }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("constructor-resources-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn target(dir: &Path, resources: &[String]) -> TargetConfig {
+        let mut target: TargetConfig = toml::from_str(
+            r#"
+            name = "my-app"
+            out_dir = "build"
+            kind = "executable"
+            sources = ["main.c"]
+            os_target = "linux"
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        target.out_dir = dir.join("build").to_string_lossy().to_string();
+        target.resources = Some(resources.to_vec());
+        target
+    }
+
+    #[test]
+    fn makes_identifiers() {
+        assert_eq!(identifier("assets/index.html"), "assets_index_html");
+        assert_eq!(identifier("my-app"), "my_app");
+        assert_eq!(identifier("3d/model.obj"), "_3d_model_obj");
+    }
+
+    #[test]
+    fn generates_source_and_header() {
+        let dir = temp_dir("generate");
+        let file = dir.join("hi.txt");
+        fs::write(&file, "hi\n").unwrap();
+        let target = target(&dir, &[file.to_string_lossy().to_string()]);
+        let name = identifier(&file.to_string_lossy());
+
+        let generated = generate(&target, false).unwrap().unwrap();
+        assert_eq!(generated.include_dir, gen_dir(&target));
+        assert_eq!(generated.source, format!("{}/my_app_resources.c", gen_dir(&target)));
+        let source = fs::read_to_string(&generated.source).unwrap();
+        assert!(source.contains("#include \"my_app_resources.h\"\n"), "{}", source);
+        assert!(source.contains(&format!("const unsigned char {}[] = {{\n    0x68, 0x69, 0x0a, 0x00,\n}};\n", name)), "{}", source);
+        assert!(source.contains(&format!("const size_t {}_size = 3;\n", name)), "{}", source);
+        let header = fs::read_to_string(format!("{}/my_app_resources.h", generated.include_dir)).unwrap();
+        assert!(header.contains(&format!("extern const unsigned char {}[];\nextern const size_t {}_size;\n", name, name)));
+        assert!(header.contains("extern \"C\""));
+
+        // touch без правки не перезаписывает сгенерированные файлы
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        fs::File::options().write(true).open(&generated.source).unwrap().set_modified(old).unwrap();
+        fs::File::options().write(true).open(&file).unwrap().set_modified(SystemTime::now()).unwrap();
+        generate(&target, false).unwrap();
+        assert_eq!(fs::metadata(&generated.source).unwrap().modified().unwrap(), old);
+
+        fs::write(&file, "hey\n").unwrap();
+        generate(&target, false).unwrap();
+        assert!(fs::read_to_string(&generated.source).unwrap().contains(&format!("const size_t {}_size = 4;\n", name)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_and_clashing_resources() {
+        let dir = temp_dir("errors");
+        assert!(generate(&target(&dir, &[]), false).unwrap().is_none());
+
+        let missing = dir.join("missing.txt").to_string_lossy().to_string();
+        let error = generate(&target(&dir, std::slice::from_ref(&missing)), false).err().unwrap();
+        assert_eq!(error.to_string(), format!("Target 'my-app': resource {} does not exist", missing));
+
+        fs::write(dir.join("a.b"), "").unwrap();
+        fs::write(dir.join("a_b"), "").unwrap();
+        let pattern = dir.join("a?b").to_string_lossy().to_string();
+        let error = generate(&target(&dir, &[pattern]), false).err().unwrap();
+        assert!(error.to_string().contains("and another file both map to"), "{}", error);
+        assert!(!Path::new(&gen_dir(&target(&dir, &[]))).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
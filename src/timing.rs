@@ -0,0 +1,100 @@
+use anyhow::Result;
+use log::info;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Сколько самых долгих шагов показывается в отчёте после сборки.
+const SLOWEST_STEPS: usize = 10;
+
+/// Один шаг сборки: загрузка зависимости, скрипт, компиляция, архивация или линковка.
+struct Span {
+    /// Вид шага: `fetch`, `script`, `compile`, `archive`, `link`...
+    category: &'static str,
+    /// Таргет или зависимость, к которой относится шаг
+    unit: String,
+    name: String,
+    start: Duration,
+    duration: Duration,
+}
+
+/// Время шагов сборки. Шаги пишутся из параллельных потоков, поэтому список под мьютексом.
+pub struct Timings {
+    origin: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self { origin: Instant::now(), spans: Mutex::new(vec![]) }
+    }
+
+    /// Выполняет шаг и запоминает, когда он начался и закончился, в том числе если он упал.
+    pub fn record<T>(&self, category: &'static str, unit: &str, name: &str, step: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = step();
+        let span = Span {
+            category,
+            unit: unit.to_string(),
+            name: name.to_string(),
+            start: start - self.origin,
+            duration: start.elapsed(),
+        };
+        self.spans.lock().unwrap_or_else(|e| e.into_inner()).push(span);
+        result
+    }
+
+    /// Печатает общее время и самые долгие шаги. Шаги таргета целиком (`target`) в список
+    /// не попадают: они включают в себя остальные.
+    pub fn print_summary(&self) {
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let mut steps: Vec<&Span> = spans.iter().filter(|span| span.category != "target").collect();
+        if steps.is_empty() {
+            return;
+        }
+        steps.sort_by_key(|span| std::cmp::Reverse(span.duration));
+        info!("Finished in {:.2}s, slowest steps:", self.origin.elapsed().as_secs_f64());
+        for span in steps.iter().take(SLOWEST_STEPS) {
+            info!(
+                target = span.unit.as_str();
+                "{:>8.2}s  {} {}",
+                span.duration.as_secs_f64(),
+                span.category,
+                span.name
+            );
+        }
+    }
+
+    /// Записывает шаги в формате Chrome trace event, который открывают Perfetto и
+    /// chrome://tracing. У каждого таргета и зависимости своя дорожка.
+    pub fn write_trace(&self, path: &str) -> Result<()> {
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let mut lanes: BTreeMap<&str, usize> = BTreeMap::new();
+        for span in spans.iter() {
+            let next = lanes.len() + 1;
+            lanes.entry(span.unit.as_str()).or_insert(next);
+        }
+        let mut events: Vec<serde_json::Value> = lanes
+            .iter()
+            .map(|(unit, tid)| json!({"name": "thread_name", "ph": "M", "pid": 1, "tid": tid, "args": {"name": unit}}))
+            .collect();
+        events.extend(spans.iter().map(|span| {
+            json!({
+                "name": span.name,
+                "cat": span.category,
+                "ph": "X",
+                "ts": span.start.as_micros() as u64,
+                "dur": span.duration.as_micros() as u64,
+                "pid": 1,
+                "tid": lanes[span.unit.as_str()],
+            })
+        }));
+        let trace = json!({"traceEvents": events, "displayTimeUnit": "ms"});
+        fs::write(path, serde_json::to_string(&trace)? + "\n")
+            .map_err(|e| anyhow::anyhow!("Failed to write trace {}: {}", path, e))?;
+        info!("Wrote build trace to {}", path);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::config::{Dependency, TargetConfig};
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+/// Требования использования узла (зависимости или библиотечного таргета),
+/// которые наследует всё, что от него зависит, в том числе транзитивно.
+#[derive(Debug, Clone, Default)]
+pub struct UsageRequirements
+{
+    pub name: String,
+    pub includes: Vec<String>,
+    pub defines: Vec<String>,
+    /// Аргументы линковки: файлы библиотек и флаги -l
+    pub libs: Vec<String>,
+    /// Узлы, от которых зависит этот узел
+    pub deps: Vec<String>,
+    /// Отпечаток содержимого, входит в ключ кеша потребителей
+    pub fingerprint: u64,
+}
+
+impl UsageRequirements {
+    /// Требования зависимости; пути из конфига разрешаются относительно `root`
+    /// (для собираемых зависимостей — относительно результата сборки).
+    pub fn for_dependency(dep: &Dependency, root: Option<&Path>, fingerprint: u64) -> Self {
+        let resolve = |path: &String| match root {
+            Some(root) => root.join(path).to_string_lossy().to_string(),
+            None => path.clone(),
+        };
+        // По умолчанию экспортируется директория include/, если она есть
+        let includes = match (&dep.includes, root) {
+            (Some(includes), _) => includes.iter().map(resolve).collect(),
+            (None, Some(root)) if root.join("include").is_dir() => {
+                vec![root.join("include").to_string_lossy().to_string()]
+            },
+            (None, _) => vec![],
+        };
+        let outputs = dep.build.iter().flat_map(|build| build.outputs.iter().flatten());
+        let mut libs: Vec<String> = outputs.chain(dep.libs.iter().flatten()).map(resolve).collect();
+        libs.extend(dep.link_libraries.iter().flatten().map(|lib| link_argument(lib)));
+        Self {
+            name: dep.name.clone(),
+            includes,
+            defines: dep.defines.clone().unwrap_or_default(),
+            libs,
+            deps: dep.deps.clone().unwrap_or_default(),
+            fingerprint,
+        }
+    }
+
+    /// Требования библиотечного таргета. Вызывается после его сборки,
+    /// чтобы содержимое артефакта попало в отпечаток. `deps` — узлы, с которыми таргет
+    /// собирался на самом деле: без явных deps это все [[dependencies]].
+    pub fn for_target(target: &TargetConfig, root: &Path, deps: Vec<String>) -> Result<Self> {
+        let output = root.join(target.output_path());
+        let mut hasher = DefaultHasher::new();
+        if let Ok(content) = fs::read(&output) {
+            content.hash(&mut hasher);
+        }
+        let mut libs = vec![output.to_string_lossy().to_string()];
+        libs.extend(target.public_link_libraries.iter().flatten().map(|lib| link_argument(lib)));
+        Ok(Self {
+            name: target.name.clone(),
+            includes: target
+                .public_includes
+                .iter()
+                .flatten()
+                .map(|include| root.join(include).to_string_lossy().to_string())
+                .collect(),
+            defines: target.public_defines.clone().unwrap_or_default(),
+            libs,
+            deps,
+            fingerprint: hasher.finish(),
+        })
+    }
+
+    /// Добавляет требования другого узла (например, таргета из WORKSPACE локальной зависимости).
+    /// Отпечатки хешируются вместе, а не складываются через XOR: одинаковые отпечатки
+    /// не должны взаимно уничтожаться.
+    pub fn merge(&mut self, other: UsageRequirements) {
+        for include in other.includes {
+            if !self.includes.contains(&include) {
+                self.includes.push(include);
+            }
+        }
+        self.defines.extend(other.defines);
+        self.libs.extend(other.libs);
+        let mut hasher = DefaultHasher::new();
+        (self.fingerprint, other.fingerprint).hash(&mut hasher);
+        self.fingerprint = hasher.finish();
+    }
+
+    pub fn hash_into(&self, hasher: &mut DefaultHasher) {
+        self.name.hash(hasher);
+        self.includes.hash(hasher);
+        self.defines.hash(hasher);
+        self.libs.hash(hasher);
+        self.fingerprint.hash(hasher);
+    }
+}
+
+/// "z" превращается в "-lz"; пути к файлам и готовые флаги передаются как есть.
+pub fn link_argument(lib: &str) -> String {
+    if lib.starts_with('-') || lib.contains('/') || lib.ends_with(".a") || lib.ends_with(".so") || lib.ends_with(".dylib") {
+        lib.to_string()
+    } else {
+        format!("-l{}", lib)
+    }
+}
+
+/// Узлы, с которыми собирается таргет: его deps, а без них — все [[dependencies]].
+pub fn roots(target: &TargetConfig, all_dependencies: &[String]) -> Vec<String> {
+    target.deps.clone().unwrap_or_else(|| all_dependencies.to_vec())
+}
+
+/// Собирает требования всех узлов, достижимых из `roots`, в порядке линковки:
+/// каждый узел идёт раньше тех, от кого он зависит.
+pub fn collect(
+    consumer: &str,
+    roots: &[String],
+    nodes: &HashMap<String, UsageRequirements>,
+) -> Result<Vec<UsageRequirements>> {
+    fn visit(
+        consumer: &str,
+        name: &str,
+        nodes: &HashMap<String, UsageRequirements>,
+        visiting: &mut HashSet<String>,
+        visited: &mut HashSet<String>,
+        order: &mut Vec<UsageRequirements>,
+    ) -> Result<()> {
+        if visited.contains(name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_string()) {
+            anyhow::bail!("Dependency cycle detected at '{}' (required by '{}')", name, consumer);
+        }
+        let node = nodes
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("'{}' depends on unknown dependency or library target '{}'", consumer, name))?;
+        for dep in &node.deps {
+            visit(name, dep, nodes, visiting, visited, order)?;
+        }
+        visiting.remove(name);
+        visited.insert(name.to_string());
+        order.push(node.clone());
+        Ok(())
+    }
+
+    let mut visiting = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut order = vec![];
+    for root in roots {
+        visit(consumer, root, nodes, &mut visiting, &mut visited, &mut order)?;
+    }
+    order.reverse();
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(items: Vec<UsageRequirements>) -> HashMap<String, UsageRequirements> {
+        items.into_iter().map(|item| (item.name.clone(), item)).collect()
+    }
+
+    #[test]
+    fn library_without_deps_passes_all_dependencies_to_consumers() {
+        let dep: Dependency = toml::from_str(
+            r#"
+            name = "libm"
+            source = "system"
+            link_libraries = ["m"]
+            "#,
+        )
+        .unwrap();
+        let core: TargetConfig = toml::from_str(
+            r#"
+            name = "core"
+            out_dir = "build"
+            kind = "staticlib"
+            sources = ["core.c"]
+            os_target = "linux"
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        // Без явных deps core собирается со всеми [[dependencies]]
+        let roots = roots(&core, &["libm".to_string()]);
+        assert_eq!(roots, ["libm"]);
+        let core_usage = UsageRequirements::for_target(&core, Path::new(""), roots).unwrap();
+        let nodes = nodes(vec![UsageRequirements::for_dependency(&dep, None, 0), core_usage]);
+
+        let usage = collect("app", &["core".to_string()], &nodes).unwrap();
+        let names: Vec<&str> = usage.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, ["core", "libm"]);
+        let libs: Vec<&str> = usage.iter().flat_map(|node| node.libs.iter().map(String::as_str)).collect();
+        assert_eq!(libs, ["build/libcore.a", "-lm"]);
+    }
+
+    #[test]
+    fn collect_orders_dependents_before_dependencies() {
+        let node = |name: &str, deps: &[&str]| UsageRequirements {
+            name: name.to_string(),
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            ..Default::default()
+        };
+        let nodes = nodes(vec![node("a", &["b", "c"]), node("b", &["c"]), node("c", &[])]);
+        let usage = collect("app", &["a".to_string()], &nodes).unwrap();
+        let names: Vec<&str> = usage.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn collect_reports_cycles() {
+        let node = |name: &str, dep: &str| UsageRequirements { name: name.to_string(), deps: vec![dep.to_string()], ..Default::default() };
+        let nodes = nodes(vec![node("a", "b"), node("b", "a")]);
+        let error = collect("app", &["a".to_string()], &nodes).unwrap_err();
+        assert!(error.to_string().contains("Dependency cycle"), "{}", error);
+    }
+
+    #[test]
+    fn merge_keeps_equal_fingerprints() {
+        let node = |fingerprint| UsageRequirements { name: "dep".to_string(), fingerprint, ..Default::default() };
+        let merged = |fingerprints: &[u64]| {
+            let mut usage = node(1);
+            for fingerprint in fingerprints {
+                usage.merge(node(*fingerprint));
+            }
+            usage.fingerprint
+        };
+        // Один и тот же узел, достигнутый дважды, не обнуляет отпечаток
+        assert_ne!(merged(&[7, 7]), merged(&[]));
+        assert_ne!(merged(&[7, 7]), merged(&[8, 8]));
+        assert_ne!(merged(&[7]), merged(&[8]));
+    }
+
+    #[test]
+    fn link_argument_keeps_paths_and_flags() {
+        assert_eq!(link_argument("z"), "-lz");
+        assert_eq!(link_argument("-pthread"), "-pthread");
+        assert_eq!(link_argument("lib/libfoo.a"), "lib/libfoo.a");
+    }
+}
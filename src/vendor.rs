@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Файл внутри завендоренной зависимости, в котором хранится её зафиксированная ревизия.
+pub const VENDOR_MARKER: &str = ".constructor-vendor";
+
+/// Ревизия завендоренной копии: коммит и `rev` из конфига, в который он был разрешён.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendoredRevision
+{
+    pub commit: String,
+    pub rev: Option<String>,
+}
+
+impl VendoredRevision {
+    /// Копия подходит для `rev` из конфига: это тот же ref или тот же (в том числе сокращённый) коммит.
+    pub fn matches(&self, rev: &str) -> bool {
+        self.rev.as_deref() == Some(rev) || (rev.len() >= 4 && self.commit.starts_with(rev))
+    }
+}
+
+impl fmt::Display for VendoredRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rev {
+            Some(rev) if *rev != self.commit => write!(f, "{} ({})", rev, self.commit),
+            _ => write!(f, "{}", self.commit),
+        }
+    }
+}
+
+/// Ревизия, записанная в завендоренной копии зависимости, если она есть:
+/// коммит в первой строке, ref из конфига — во второй.
+pub fn vendored_revision(dir: &Path) -> Option<VendoredRevision> {
+    let content = fs::read_to_string(dir.join(VENDOR_MARKER)).ok()?;
+    let mut lines = content.lines().map(str::trim).filter(|line| !line.is_empty());
+    let commit = lines.next()?.to_string();
+    let rev = lines.next().map(str::to_string);
+    Some(VendoredRevision { commit, rev })
+}
+
+pub fn write_vendor_marker(dir: &Path, revision: &VendoredRevision) -> Result<()> {
+    let mut content = format!("{}\n", revision.commit);
+    if let Some(rev) = &revision.rev {
+        content.push_str(&format!("{}\n", rev));
+    }
+    fs::write(dir.join(VENDOR_MARKER), content)?;
+    Ok(())
+}
+
+/// Полный хеш коммита, на который указывает `rev` в git-репозитории `repo`.
+pub fn resolve_git_revision(repo: &Path, rev: &str) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .arg("rev-parse")
+        .arg(format!("{}^{{commit}}", rev))
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!("Failed to resolve revision '{}' in {}", rev, repo.display());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Выгружает дерево файлов git-репозитория на ревизии `rev` в `dest` (без .git).
+pub fn export_git_tree(repo: &Path, rev: &str, dest: &Path) -> Result<()> {
+    let mut child = Command::new("git")
+        .current_dir(repo)
+        .arg("archive")
+        .arg("--format=tar")
+        .arg(rev)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("Failed to read git archive output"))?;
+    fs::create_dir_all(dest)?;
+    tar::Archive::new(stdout).unpack(dest)?;
+    // stderr не выводится напрямую, чтобы не ломать лог; при ошибке он попадает в её текст
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git archive failed for {} at {}: {}",
+            repo.display(),
+            rev,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Рекурсивно копирует директорию. Директория .git и файлы, для которых `skip` вернул true, не копируются.
+/// Символические ссылки (частые в релизных архивах) копируются как ссылки, а не как их содержимое.
+pub fn copy_dir(src: &Path, dest: &Path, skip: &dyn Fn(&Path) -> bool) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        let target = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            if skip(&path) {
+                continue;
+            }
+            if fs::symlink_metadata(&target).is_ok() {
+                fs::remove_file(&target)?;
+            }
+            std::os::unix::fs::symlink(fs::read_link(&path)?, &target)?;
+        } else if file_type.is_dir() {
+            copy_dir(&path, &target, skip)?;
+        } else if !skip(&path) {
+            fs::copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_dir_recreates_symlinks() {
+        let dir = std::env::temp_dir().join(format!("constructor-vendor-{}-{}", std::process::id(), "symlinks"));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("include/v1")).unwrap();
+        fs::write(src.join("include/v1/lib.h"), "#define V 1\n").unwrap();
+        fs::write(src.join("libfoo.so.1"), "so").unwrap();
+        fs::write(src.join("foo.o"), "object").unwrap();
+        std::os::unix::fs::symlink("v1", src.join("include/current")).unwrap();
+        std::os::unix::fs::symlink("libfoo.so.1", src.join("libfoo.so")).unwrap();
+        std::os::unix::fs::symlink("missing", src.join("dangling")).unwrap();
+
+        let dest = dir.join("dest");
+        for _ in 0..2 {
+            copy_dir(&src, &dest, &|path| path.extension().is_some_and(|extension| extension == "o")).unwrap();
+        }
+        assert_eq!(fs::read_link(dest.join("include/current")).unwrap(), Path::new("v1"));
+        assert_eq!(fs::read_to_string(dest.join("include/current/lib.h")).unwrap(), "#define V 1\n");
+        assert_eq!(fs::read_link(dest.join("libfoo.so")).unwrap(), Path::new("libfoo.so.1"));
+        assert_eq!(fs::read_link(dest.join("dangling")).unwrap(), Path::new("missing"));
+        assert!(!dest.join("foo.o").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}